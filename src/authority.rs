@@ -0,0 +1,268 @@
+use std::collections::BTreeSet;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs;
+use std::path::Path;
+
+use libp2p::identity::ed25519::{Keypair, PublicKey};
+use serde::{de, Deserialize, Deserializer, Serialize, Serializer};
+
+use crate::block::BlockHash;
+use crate::errors::Error;
+
+const AUTHORITY_CHANGE_DOMAIN: &[u8] = b"monkey/authority-change";
+
+fn encode_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Bytes are written as hex strings in JSON files, and as they are in
+/// blocks.
+fn serialize_bytes<S: Serializer>(bytes: &[u8], serializer: S) -> Result<S::Ok, S::Error> {
+    match serializer.is_human_readable() {
+        true => serializer.serialize_str(&encode_hex(bytes)),
+        false => bytes.to_vec().serialize(serializer),
+    }
+}
+
+fn deserialize_bytes<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Vec<u8>, D::Error> {
+    match deserializer.is_human_readable() {
+        true => {
+            let hex = String::deserialize(deserializer)?;
+            decode_hex(&hex).ok_or_else(|| de::Error::custom(format!("invalid hex {:?}", hex)))
+        }
+        false => Vec::deserialize(deserializer),
+    }
+}
+
+/// Ed25519 public key of a proposer allowed to produce blocks, in the
+/// encoding of `BlockHeader::proposer`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct AuthorityKey(pub [u8; 32]);
+
+impl From<&PublicKey> for AuthorityKey {
+    fn from(public_key: &PublicKey) -> Self {
+        AuthorityKey(public_key.encode())
+    }
+}
+
+impl fmt::Display for AuthorityKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", encode_hex(&self.0))
+    }
+}
+
+impl fmt::Debug for AuthorityKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+impl Serialize for AuthorityKey {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serialize_bytes(&self.0, serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for AuthorityKey {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let bytes = deserialize_bytes(deserializer)?;
+        let len = bytes.len();
+
+        let key = bytes
+            .try_into()
+            .map_err(|_| de::Error::invalid_length(len, &"a 32 byte public key"))?;
+
+        Ok(AuthorityKey(key))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AuthoritySignature {
+    pub authority: AuthorityKey,
+    #[serde(
+        serialize_with = "serialize_bytes",
+        deserialize_with = "deserialize_bytes"
+    )]
+    pub signature: Vec<u8>,
+}
+
+/// Replaces the authority set from the block carrying it on, once signed
+/// by a majority of the current authorities. Changes are numbered so that
+/// an old one cannot be replayed, written to a JSON file such as
+///
+/// ```json
+/// {
+///   "sequence": 1,
+///   "authorities": ["6b1e...", "f03a..."],
+///   "signatures": []
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct AuthorityChange {
+    /// One more than the number of changes made before.
+    pub sequence: u64,
+    pub authorities: Vec<AuthorityKey>,
+
+    #[serde(default)]
+    pub signatures: Vec<AuthoritySignature>,
+}
+
+impl AuthorityChange {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)?;
+
+        serde_json::from_str(&json).map_err(|e| Error::InvalidAuthorityChange(e.to_string()))
+    }
+
+    pub fn save(&self, path: &Path) -> Result<(), Error> {
+        let json = serde_json::to_string_pretty(self).unwrap();
+
+        Ok(fs::write(path, json)?)
+    }
+
+    /// What the authorities sign, leaving out the signatures themselves.
+    fn message(&self) -> BlockHash {
+        let mut encoded = AUTHORITY_CHANGE_DOMAIN.to_vec();
+        encoded.extend_from_slice(&self.sequence.to_be_bytes());
+        for authority in self.authorities.iter() {
+            encoded.extend_from_slice(&authority.0);
+        }
+
+        BlockHash::digest(&encoded)
+    }
+
+    /// Digest over the change and its signatures, committed to by the
+    /// header of the block carrying it.
+    pub fn digest(&self) -> BlockHash {
+        let mut encoded = self.message().as_bytes().to_vec();
+        for signature in self.signatures.iter() {
+            encoded.extend_from_slice(&signature.authority.0);
+            encoded.extend_from_slice(&signature.signature);
+        }
+
+        BlockHash::digest(&encoded)
+    }
+
+    /// Adds or replaces the signature of `keypair`.
+    pub fn sign(&mut self, keypair: &Keypair) {
+        let authority = AuthorityKey::from(&keypair.public());
+        let signature = keypair.sign(self.message().as_bytes());
+
+        self.signatures.retain(|s| s.authority != authority);
+        self.signatures.push(AuthoritySignature {
+            authority: authority,
+            signature: signature,
+        });
+    }
+
+    /// Checks that the change follows change number `sequence - 1` and is
+    /// signed by more than half of the `current` authorities.
+    pub fn verify(&self, current: &[AuthorityKey], sequence: u64) -> Result<(), Error> {
+        if self.sequence != sequence {
+            return Err(Error::InvalidAuthorityChange(format!(
+                "change {} where {} was expected",
+                self.sequence, sequence
+            )));
+        }
+
+        let authorities: BTreeSet<_> = self.authorities.iter().collect();
+        if authorities.is_empty() || authorities.len() != self.authorities.len() {
+            return Err(Error::InvalidAuthorityChange(
+                "empty or repeated authorities".into(),
+            ));
+        }
+
+        let message = self.message();
+        let mut signers = BTreeSet::new();
+        for signature in self.signatures.iter() {
+            let valid = current.contains(&signature.authority)
+                && PublicKey::decode(&signature.authority.0)
+                    .is_ok_and(|pk| pk.verify(message.as_bytes(), &signature.signature));
+            if !valid || !signers.insert(signature.authority) {
+                return Err(Error::InvalidAuthorityChange(format!(
+                    "invalid signature by {}",
+                    signature.authority
+                )));
+            }
+        }
+
+        if signers.len() * 2 <= current.len() {
+            return Err(Error::InvalidAuthorityChange(format!(
+                "signed by {} of {} authorities",
+                signers.len(),
+                current.len()
+            )));
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_authority_change() {
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate()).collect();
+        let current: Vec<AuthorityKey> = keypairs
+            .iter()
+            .map(|keypair| AuthorityKey::from(&keypair.public()))
+            .collect();
+
+        let mut change = AuthorityChange {
+            sequence: 1,
+            authorities: current[..2].to_vec(),
+            signatures: Vec::new(),
+        };
+
+        change.sign(&keypairs[0]);
+        change.sign(&keypairs[0]);
+        assert!(matches!(
+            change.verify(&current, 1),
+            Err(Error::InvalidAuthorityChange(_))
+        ));
+
+        change.sign(&keypairs[1]);
+        assert_eq!(change.verify(&current, 1), Ok(()));
+        assert!(change.verify(&current, 2).is_err());
+
+        // Signatures of keys outside the current set do not count
+        let outsider = Keypair::generate();
+        let mut forged = change.clone();
+        forged.sign(&outsider);
+        assert!(forged.verify(&current, 1).is_err());
+
+        let mut tampered = change.clone();
+        tampered
+            .authorities
+            .push(AuthorityKey::from(&outsider.public()));
+        assert!(tampered.verify(&current, 1).is_err());
+
+        // Keys and signatures are hex in JSON
+        let json = serde_json::to_string(&change).unwrap();
+        assert!(json.contains(&current[0].to_string()));
+        assert_eq!(
+            serde_json::from_str::<AuthorityChange>(&json).unwrap(),
+            change
+        );
+
+        let bytes = bincode::serialize(&change).unwrap();
+        assert_eq!(
+            bincode::deserialize::<AuthorityChange>(&bytes).unwrap(),
+            change
+        );
+    }
+}
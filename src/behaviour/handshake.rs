@@ -0,0 +1,214 @@
+use std::collections::VecDeque;
+use std::task::{Context, Poll};
+use std::{io, iter, pin::Pin};
+
+use bincode::{deserialize, serialize};
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    Future,
+};
+use libp2p::{
+    core::{connection::ConnectionId, upgrade, InboundUpgrade, OutboundUpgrade, UpgradeInfo},
+    swarm::{
+        NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, OneShotHandler, PollParameters,
+        ProtocolsHandler,
+    },
+    Multiaddr, PeerId,
+};
+
+use crate::behaviour::types::{Incompatibility, Status};
+use crate::block::BlockHash;
+
+const STATUS_PROTOCOL: &[u8] = b"/monkey/status/1.0.0";
+const MAX_STATUS_SIZE: usize = 1024;
+
+/// Inbound side of the status protocol, every substream carries one `Status`.
+#[derive(Clone, Debug, Default)]
+pub struct StatusProtocol;
+
+impl UpgradeInfo for StatusProtocol {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(STATUS_PROTOCOL)
+    }
+}
+
+impl<TSocket> InboundUpgrade<TSocket> for StatusProtocol
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = Status;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_inbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let packet = upgrade::read_one(&mut socket, MAX_STATUS_SIZE)
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            deserialize(&packet).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+    }
+}
+
+impl UpgradeInfo for Status {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(STATUS_PROTOCOL)
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for Status
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = ();
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_outbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let bytes =
+                serialize(&self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            upgrade::write_one(&mut socket, bytes).await?;
+
+            Ok(())
+        })
+    }
+}
+
+pub enum InnerMessage {
+    Rx(Status),
+    Sent,
+}
+
+impl From<Status> for InnerMessage {
+    fn from(status: Status) -> InnerMessage {
+        InnerMessage::Rx(status)
+    }
+}
+
+impl From<()> for InnerMessage {
+    fn from(_: ()) -> InnerMessage {
+        InnerMessage::Sent
+    }
+}
+
+#[derive(Debug)]
+pub enum HandshakeEvent {
+    Compatible(PeerId, Status),
+    Incompatible(PeerId, Incompatibility),
+}
+
+/// Network behaviour sending our `Status` to every newly connected peer and
+/// checking theirs against it.
+pub struct Handshake {
+    events: VecDeque<NetworkBehaviourAction<Status, HandshakeEvent>>,
+
+    // Peers connected before the local chain head was known
+    pending: Vec<PeerId>,
+    local: Option<Status>,
+    network: String,
+    spec_digest: BlockHash,
+}
+
+impl Handshake {
+    pub fn new(network: &str, spec_digest: BlockHash) -> Self {
+        Handshake {
+            events: VecDeque::new(),
+            pending: Vec::new(),
+            local: None,
+            network: network.to_string(),
+            spec_digest: spec_digest,
+        }
+    }
+
+    pub fn local_status(&self) -> Option<&Status> {
+        self.local.as_ref()
+    }
+
+    pub fn set_head(&mut self, head: BlockHash, height: u64, weight: u64) {
+        self.local = Some(Status::new(
+            &self.network,
+            self.spec_digest,
+            head,
+            height,
+            weight,
+        ));
+
+        for peer_id in std::mem::take(&mut self.pending) {
+            self.send_status(&peer_id);
+        }
+    }
+
+    fn send_status(&mut self, peer_id: &PeerId) {
+        match &self.local {
+            Some(status) => self
+                .events
+                .push_back(NetworkBehaviourAction::NotifyHandler {
+                    peer_id: peer_id.clone(),
+                    handler: NotifyHandler::Any,
+                    event: status.clone(),
+                }),
+            None => self.pending.push(peer_id.clone()),
+        }
+    }
+}
+
+impl NetworkBehaviour for Handshake {
+    type ProtocolsHandler = OneShotHandler<StatusProtocol, Status, InnerMessage>;
+    type OutEvent = HandshakeEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Default::default()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.send_status(peer_id);
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.pending.retain(|pending| pending != peer_id);
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, _: ConnectionId, event: InnerMessage) {
+        let status = match event {
+            InnerMessage::Rx(status) => status,
+            InnerMessage::Sent => return,
+        };
+
+        let event = match status.check_compatible(&self.network, &self.spec_digest) {
+            Ok(()) => HandshakeEvent::Compatible(peer_id, status),
+            Err(incompatibility) => HandshakeEvent::Incompatible(peer_id, incompatibility),
+        };
+
+        self.events
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+
+        Poll::Pending
+    }
+}
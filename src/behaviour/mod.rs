@@ -6,36 +6,90 @@ use libp2p::{
     gossipsub::{
         Gossipsub, GossipsubConfigBuilder, GossipsubEvent, GossipsubMessage, MessageId, Topic,
     },
-    swarm::{NetworkBehaviourAction, NetworkBehaviourEventProcess},
-    NetworkBehaviour, PeerId,
+    identify::{Identify, IdentifyEvent},
+    identity::PublicKey,
+    kad::{record::store::MemoryStore, Kademlia, KademliaConfig, KademliaEvent},
+    mdns::{Mdns, MdnsEvent},
+    swarm::{toggle::Toggle, NetworkBehaviourAction, NetworkBehaviourEventProcess},
+    Multiaddr, NetworkBehaviour, PeerId,
 };
 
+mod handshake;
+mod sync;
 pub mod types;
+mod validation;
 
-use crate::behaviour::types::{BehaviourEvent, GossipsubMessage as DecodedMessage};
+use crate::behaviour::handshake::{Handshake, HandshakeEvent};
+use crate::behaviour::sync::{BlockSync, SyncEvent};
+use crate::behaviour::types::{
+    BehaviourEvent, GossipsubMessage as DecodedMessage, Status, SyncRequest, SyncResponse,
+};
+use crate::behaviour::validation::ValidatedGossipsub;
+use crate::block::BlockHash;
+use crate::errors::Error;
+
+const KADEMLIA_PROTOCOL: &[u8] = b"/monkey/kad/1.0.0";
+const IDENTIFY_PROTOCOL: &str = "/monkey/1.0.0";
 
 #[derive(NetworkBehaviour)]
 pub struct Behaviour {
-    gossipsub: Gossipsub,
+    gossipsub: ValidatedGossipsub,
+    handshake: Handshake,
+    sync: BlockSync,
+    kademlia: Kademlia<MemoryStore>,
+    mdns: Toggle<Mdns>,
+    identify: Identify,
 
     #[behaviour(ignore)]
     events: Vec<BehaviourEvent>,
 }
 
 impl Behaviour {
-    pub fn new(peer_id: &PeerId) -> Self {
+    pub fn new(
+        public_key: PublicKey,
+        network: &str,
+        spec_digest: BlockHash,
+        enable_mdns: bool,
+    ) -> Self {
+        let peer_id = PeerId::from(public_key.clone());
+
         let message_id_fn = |message: &GossipsubMessage| {
             let mut s = DefaultHasher::new();
             message.data.hash(&mut s);
             MessageId(s.finish().to_string())
         };
 
+        // Messages are only forwarded once the handler accepted them
         let gossipsub_config = GossipsubConfigBuilder::new()
             .message_id_fn(message_id_fn)
+            .manual_propagation()
             .build();
 
+        let mut kademlia_config = KademliaConfig::default();
+        kademlia_config.set_protocol_name(KADEMLIA_PROTOCOL);
+        let store = MemoryStore::new(peer_id.clone());
+
+        // mDNS is best effort, some environments do not allow multicast
+        let mdns = match enable_mdns {
+            true => match Mdns::new() {
+                Ok(mdns) => Some(mdns),
+                Err(e) => {
+                    warn!("Failed to start mDNS discovery: {:?}", e);
+                    None
+                }
+            },
+            false => None,
+        };
+
+        let agent_version = format!("monkey/{}", env!("CARGO_PKG_VERSION"));
+
         Behaviour {
-            gossipsub: Gossipsub::new(peer_id.clone(), gossipsub_config),
+            gossipsub: ValidatedGossipsub::new(Gossipsub::new(peer_id.clone(), gossipsub_config)),
+            handshake: Handshake::new(network, spec_digest),
+            sync: BlockSync::new(),
+            kademlia: Kademlia::with_config(peer_id, store, kademlia_config),
+            mdns: Toggle::from(mdns),
+            identify: Identify::new(IDENTIFY_PROTOCOL.to_string(), agent_version, public_key),
             events: Vec::<BehaviourEvent>::new(),
         }
     }
@@ -44,6 +98,11 @@ impl Behaviour {
         self.gossipsub.propagate_message(&message_id, &source)
     }
 
+    /// Keeps a message that failed validation from being gossiped further.
+    pub fn reject_message(&mut self, message_id: MessageId) {
+        self.gossipsub.reject(message_id)
+    }
+
     pub fn subscribe(&mut self, topic: &Topic) -> bool {
         self.gossipsub.subscribe(topic.clone())
     }
@@ -52,6 +111,32 @@ impl Behaviour {
         self.gossipsub.publish(&topic, lines)
     }
 
+    pub fn add_bootnode(&mut self, peer_id: &PeerId, addr: Multiaddr) {
+        self.kademlia.add_address(peer_id, addr);
+    }
+
+    /// Looks up the peers closest to us, starting from the known bootnodes.
+    pub fn bootstrap(&mut self) {
+        self.kademlia.bootstrap();
+    }
+
+    pub fn local_status(&self) -> Option<&Status> {
+        self.handshake.local_status()
+    }
+
+    /// Updates the head announced to peers in the status handshake.
+    pub fn set_head(&mut self, head: BlockHash, height: u64, weight: u64) {
+        self.handshake.set_head(head, height, weight)
+    }
+
+    pub fn send_sync_request(&mut self, peer_id: &PeerId, id: u64, request: SyncRequest) {
+        self.sync.send_request(peer_id, id, request)
+    }
+
+    pub fn send_sync_response(&mut self, peer_id: &PeerId, id: u64, response: SyncResponse) {
+        self.sync.send_response(peer_id, id, response)
+    }
+
     pub fn poll<TBehaviourIn>(
         &mut self,
     ) -> Poll<NetworkBehaviourAction<TBehaviourIn, BehaviourEvent>> {
@@ -75,8 +160,18 @@ impl NetworkBehaviourEventProcess<GossipsubEvent> for Behaviour {
                             message: msg,
                         });
                     }
+                    // Most likely sent by a newer peer, not worth a penalty
+                    Err(Error::UnknownMessageType(_))
+                    | Err(Error::UnsupportedEnvelopeVersion(_)) => {
+                        debug!(
+                            "Ignoring unsupported Gossipsub message {} from {}",
+                            id, source
+                        );
+                    }
                     Err(e) => {
                         error!("Failed to decode Gossipsub message: {:?}", e);
+
+                        self.events.push(BehaviourEvent::InvalidMessage(source));
                     }
                 }
             }
@@ -91,3 +186,90 @@ impl NetworkBehaviourEventProcess<GossipsubEvent> for Behaviour {
         }
     }
 }
+
+impl NetworkBehaviourEventProcess<KademliaEvent> for Behaviour {
+    fn inject_event(&mut self, event: KademliaEvent) {
+        match event {
+            KademliaEvent::Discovered { peer_id, .. } => {
+                self.events.push(BehaviourEvent::PeerDiscovered(peer_id));
+            }
+            KademliaEvent::BootstrapResult(result) => {
+                debug!("Kademlia bootstrap finished: {:?}", result);
+            }
+            _ => {}
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<MdnsEvent> for Behaviour {
+    fn inject_event(&mut self, event: MdnsEvent) {
+        if let MdnsEvent::Discovered(discovered) = event {
+            for (peer_id, addr) in discovered {
+                debug!("Discovered {} at {} via mDNS", peer_id, addr);
+
+                self.kademlia.add_address(&peer_id, addr);
+                self.events.push(BehaviourEvent::PeerDiscovered(peer_id));
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<IdentifyEvent> for Behaviour {
+    fn inject_event(&mut self, event: IdentifyEvent) {
+        if let IdentifyEvent::Received { peer_id, info, .. } = event {
+            // Only identified peers speaking our protocol are added to the
+            // routing table, under the addresses they are listening on
+            if info.protocol_version == IDENTIFY_PROTOCOL {
+                for addr in info.listen_addrs {
+                    self.kademlia.add_address(&peer_id, addr);
+                }
+            }
+        }
+    }
+}
+
+impl NetworkBehaviourEventProcess<HandshakeEvent> for Behaviour {
+    fn inject_event(&mut self, event: HandshakeEvent) {
+        let event = match event {
+            HandshakeEvent::Compatible(peer_id, status) => {
+                BehaviourEvent::PeerStatus(peer_id, status)
+            }
+            HandshakeEvent::Incompatible(peer_id, incompatibility) => {
+                BehaviourEvent::IncompatiblePeer(peer_id, incompatibility)
+            }
+        };
+
+        self.events.push(event);
+    }
+}
+
+impl NetworkBehaviourEventProcess<SyncEvent> for Behaviour {
+    fn inject_event(&mut self, event: SyncEvent) {
+        let event = match event {
+            SyncEvent::Connected(peer_id) => BehaviourEvent::PeerConnected(peer_id),
+            SyncEvent::Disconnected(peer_id) => BehaviourEvent::PeerDisconnected(peer_id),
+            SyncEvent::Dialed(peer_id, addr) => BehaviourEvent::PeerDialed(peer_id, addr),
+            SyncEvent::DialFailure(addr) => BehaviourEvent::DialFailure(addr),
+            SyncEvent::Request {
+                peer_id,
+                id,
+                request,
+            } => BehaviourEvent::SyncRequest {
+                peer_id,
+                id,
+                request,
+            },
+            SyncEvent::Response {
+                peer_id,
+                id,
+                response,
+            } => BehaviourEvent::SyncResponse {
+                peer_id,
+                id,
+                response,
+            },
+        };
+
+        self.events.push(event);
+    }
+}
@@ -0,0 +1,251 @@
+use std::collections::VecDeque;
+use std::task::{Context, Poll};
+use std::{io, iter, pin::Pin};
+
+use bincode::{deserialize, serialize};
+use futures::{
+    io::{AsyncRead, AsyncWrite},
+    Future,
+};
+use libp2p::{
+    core::{
+        connection::ConnectionId, upgrade, ConnectedPoint, InboundUpgrade, OutboundUpgrade,
+        UpgradeInfo,
+    },
+    swarm::{
+        NetworkBehaviour, NetworkBehaviourAction, NotifyHandler, OneShotHandler, PollParameters,
+        ProtocolsHandler,
+    },
+    Multiaddr, PeerId,
+};
+use serde::{Deserialize, Serialize};
+
+use crate::behaviour::types::{SyncRequest, SyncResponse};
+
+const SYNC_PROTOCOL: &[u8] = b"/monkey/sync/1.0.0";
+const MAX_RPC_SIZE: usize = 4 * 1024 * 1024;
+
+/// Inbound side of the sync protocol, every substream carries one `SyncRpc`.
+#[derive(Clone, Debug, Default)]
+pub struct SyncProtocol;
+
+impl UpgradeInfo for SyncProtocol {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(SYNC_PROTOCOL)
+    }
+}
+
+impl<TSocket> InboundUpgrade<TSocket> for SyncProtocol
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = SyncRpc;
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_inbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let packet = upgrade::read_one(&mut socket, MAX_RPC_SIZE)
+                .await
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+            deserialize(&packet).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+        })
+    }
+}
+
+/// Requests and responses are both sent as one-shot messages and matched
+/// up again by their id.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SyncRpc {
+    Request { id: u64, request: SyncRequest },
+    Response { id: u64, response: SyncResponse },
+}
+
+impl UpgradeInfo for SyncRpc {
+    type Info = &'static [u8];
+    type InfoIter = iter::Once<Self::Info>;
+
+    fn protocol_info(&self) -> Self::InfoIter {
+        iter::once(SYNC_PROTOCOL)
+    }
+}
+
+impl<TSocket> OutboundUpgrade<TSocket> for SyncRpc
+where
+    TSocket: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+{
+    type Output = ();
+    type Error = io::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Output, Self::Error>> + Send>>;
+
+    fn upgrade_outbound(self, mut socket: TSocket, _: Self::Info) -> Self::Future {
+        Box::pin(async move {
+            let bytes =
+                serialize(&self).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+            upgrade::write_one(&mut socket, bytes).await?;
+
+            Ok(())
+        })
+    }
+}
+
+pub enum InnerMessage {
+    Rx(SyncRpc),
+    Sent,
+}
+
+impl From<SyncRpc> for InnerMessage {
+    fn from(rpc: SyncRpc) -> InnerMessage {
+        InnerMessage::Rx(rpc)
+    }
+}
+
+impl From<()> for InnerMessage {
+    fn from(_: ()) -> InnerMessage {
+        InnerMessage::Sent
+    }
+}
+
+#[derive(Debug)]
+pub enum SyncEvent {
+    Connected(PeerId),
+    Dialed(PeerId, Multiaddr),
+    Disconnected(PeerId),
+    DialFailure(Multiaddr),
+    Request {
+        peer_id: PeerId,
+        id: u64,
+        request: SyncRequest,
+    },
+    Response {
+        peer_id: PeerId,
+        id: u64,
+        response: SyncResponse,
+    },
+}
+
+/// Network behaviour serving and fetching blocks by hash or height range.
+/// Request ids are chosen by the requester, which matches the responses up
+/// with its outstanding requests.
+pub struct BlockSync {
+    events: VecDeque<NetworkBehaviourAction<SyncRpc, SyncEvent>>,
+}
+
+impl BlockSync {
+    pub fn new() -> Self {
+        BlockSync {
+            events: VecDeque::new(),
+        }
+    }
+
+    pub fn send_request(&mut self, peer_id: &PeerId, id: u64, request: SyncRequest) {
+        self.notify(peer_id, SyncRpc::Request { id, request });
+    }
+
+    pub fn send_response(&mut self, peer_id: &PeerId, id: u64, response: SyncResponse) {
+        self.notify(peer_id, SyncRpc::Response { id, response });
+    }
+
+    fn notify(&mut self, peer_id: &PeerId, rpc: SyncRpc) {
+        self.events
+            .push_back(NetworkBehaviourAction::NotifyHandler {
+                peer_id: peer_id.clone(),
+                handler: NotifyHandler::Any,
+                event: rpc,
+            });
+    }
+}
+
+impl NetworkBehaviour for BlockSync {
+    type ProtocolsHandler = OneShotHandler<SyncProtocol, SyncRpc, InnerMessage>;
+    type OutEvent = SyncEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        Default::default()
+    }
+
+    fn addresses_of_peer(&mut self, _: &PeerId) -> Vec<Multiaddr> {
+        Vec::new()
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.events
+            .push_back(NetworkBehaviourAction::GenerateEvent(SyncEvent::Connected(
+                peer_id.clone(),
+            )));
+    }
+
+    fn inject_connection_established(
+        &mut self,
+        peer_id: &PeerId,
+        _: &ConnectionId,
+        endpoint: &ConnectedPoint,
+    ) {
+        if let ConnectedPoint::Dialer { address } = endpoint {
+            self.events
+                .push_back(NetworkBehaviourAction::GenerateEvent(SyncEvent::Dialed(
+                    peer_id.clone(),
+                    address.clone(),
+                )));
+        }
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+            SyncEvent::Disconnected(peer_id.clone()),
+        ));
+    }
+
+    fn inject_addr_reach_failure(
+        &mut self,
+        _peer_id: Option<&PeerId>,
+        addr: &Multiaddr,
+        error: &dyn std::error::Error,
+    ) {
+        debug!("Failed to reach {:?}: {}", addr, error);
+
+        self.events.push_back(NetworkBehaviourAction::GenerateEvent(
+            SyncEvent::DialFailure(addr.clone()),
+        ));
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, _: ConnectionId, event: InnerMessage) {
+        let event = match event {
+            InnerMessage::Rx(SyncRpc::Request { id, request }) => SyncEvent::Request {
+                peer_id,
+                id,
+                request,
+            },
+            InnerMessage::Rx(SyncRpc::Response { id, response }) => SyncEvent::Response {
+                peer_id,
+                id,
+                response,
+            },
+            InnerMessage::Sent => return,
+        };
+
+        self.events
+            .push_back(NetworkBehaviourAction::GenerateEvent(event));
+    }
+
+    fn poll(
+        &mut self,
+        _: &mut Context,
+        _: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        if let Some(event) = self.events.pop_front() {
+            return Poll::Ready(event);
+        }
+
+        Poll::Pending
+    }
+}
@@ -1,12 +1,31 @@
 use bincode::{deserialize, serialize};
 use libp2p::{
-    gossipsub::{MessageId, TopicHash},
-    PeerId,
+    gossipsub::{MessageId, Topic, TopicHash},
+    Multiaddr, PeerId,
 };
 use serde::{Deserialize, Serialize};
 
-use crate::block::SignedBlock;
+use crate::block::{Block, BlockHash, SignedBlock};
+use crate::dictionary::DICTIONARY_DIGEST;
 use crate::errors::Error;
+use crate::finality::CheckpointVote;
+
+const PROTOCOL_VERSION: u32 = 5;
+
+const ENVELOPE_VERSION: u8 = 1;
+const ENVELOPE_HEADER_SIZE: usize = 6;
+const MAX_MESSAGE_SIZE: usize = 4096;
+
+const BLOCK_MESSAGE: u8 = 0;
+const VOTE_MESSAGE: u8 = 1;
+
+pub const DEFAULT_NETWORK: &str = "main";
+
+/// Topic blocks are gossiped on. Nodes on another network or speaking
+/// another protocol version never see each other's blocks.
+pub fn blocks_topic(network: &str) -> Topic {
+    Topic::new(format!("/monkey/{}/{}/blocks", network, PROTOCOL_VERSION))
+}
 
 #[derive(Debug)]
 pub enum BehaviourEvent {
@@ -15,25 +34,319 @@ pub enum BehaviourEvent {
         source: PeerId,
         message: GossipsubMessage,
     },
+    InvalidMessage(PeerId),
     PeerSubscribed(PeerId, TopicHash),
     PeerUnsubscribed(PeerId, TopicHash),
+    PeerConnected(PeerId),
+    PeerDialed(PeerId, Multiaddr),
+    PeerDisconnected(PeerId),
+    DialFailure(Multiaddr),
+    PeerDiscovered(PeerId),
+    PeerStatus(PeerId, Status),
+    IncompatiblePeer(PeerId, Incompatibility),
+    SyncRequest {
+        peer_id: PeerId,
+        id: u64,
+        request: SyncRequest,
+    },
+    SyncResponse {
+        peer_id: PeerId,
+        id: u64,
+        response: SyncResponse,
+    },
+}
+
+/// Verdict on a gossiped message, which is only forwarded once accepted.
+#[derive(Debug)]
+pub enum MessageAcceptance {
+    Accept,
+    /// Invalid, the error counts against the peer that sent it.
+    Reject(Error),
+    /// Valid as far as we can tell, but not worth forwarding.
+    Ignore,
+}
+
+/// Exchanged once on every new connection, so that peers on another
+/// network are dropped before they send any blocks.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Status {
+    pub protocol_version: u32,
+    pub network: String,
+    pub genesis_hash: BlockHash,
+    pub dictionary_digest: BlockHash,
+    pub spec_digest: BlockHash,
+    pub head: BlockHash,
+    pub height: u64,
+    /// Cumulative score of the chain up to `head`.
+    pub weight: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Incompatibility {
+    ProtocolVersion(u32),
+    Network(String),
+    GenesisHash(BlockHash),
+    Dictionary(BlockHash),
+    ChainSpec(BlockHash),
+}
+
+impl Status {
+    pub fn new(
+        network: &str,
+        spec_digest: BlockHash,
+        head: BlockHash,
+        height: u64,
+        weight: u64,
+    ) -> Self {
+        let (genesis_hash, _, _) = Block::genesis_block();
+
+        Status {
+            protocol_version: PROTOCOL_VERSION,
+            network: network.to_string(),
+            genesis_hash: genesis_hash,
+            dictionary_digest: *DICTIONARY_DIGEST,
+            spec_digest: spec_digest,
+            head: head,
+            height: height,
+            weight: weight,
+        }
+    }
+
+    /// Checks a remote status against the local network parameters.
+    pub fn check_compatible(
+        &self,
+        network: &str,
+        spec_digest: &BlockHash,
+    ) -> Result<(), Incompatibility> {
+        let (genesis_hash, _, _) = Block::genesis_block();
+
+        if self.protocol_version != PROTOCOL_VERSION {
+            return Err(Incompatibility::ProtocolVersion(self.protocol_version));
+        }
+
+        if self.network != network {
+            return Err(Incompatibility::Network(self.network.clone()));
+        }
+
+        if self.genesis_hash != genesis_hash {
+            return Err(Incompatibility::GenesisHash(self.genesis_hash));
+        }
+
+        if self.dictionary_digest != *DICTIONARY_DIGEST {
+            return Err(Incompatibility::Dictionary(self.dictionary_digest));
+        }
+
+        if self.spec_digest != *spec_digest {
+            return Err(Incompatibility::ChainSpec(self.spec_digest));
+        }
+
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SyncRequest {
+    BlocksByHash(Vec<BlockHash>),
+    BlocksByRange {
+        start: u64,
+        count: u64,
+    },
+    /// Votes that finalized the peer's finalized block.
+    FinalityProof,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum SyncResponse {
+    Blocks(Vec<SignedBlock>),
+    FinalityProof(Vec<CheckpointVote>),
 }
 
 #[derive(Debug, Serialize, Deserialize)]
 pub enum GossipsubMessage {
     Block(SignedBlock),
+    Vote(CheckpointVote),
 }
 
+/// Gossip messages are framed in an envelope of a version byte, a message
+/// type tag and the big endian length of the bincode payload, so that new
+/// message types can be told apart from garbage by older peers.
 impl GossipsubMessage {
     pub fn encode(&self) -> Result<Vec<u8>, Error> {
-        let bytes: Vec<u8> = serialize(&self)?;
+        let (tag, payload) = match self {
+            GossipsubMessage::Block(signed_block) => (BLOCK_MESSAGE, serialize(signed_block)?),
+            GossipsubMessage::Vote(vote) => (VOTE_MESSAGE, serialize(vote)?),
+        };
+
+        if payload.len() > MAX_MESSAGE_SIZE {
+            return Err(Error::MessageTooLarge(payload.len()));
+        }
+
+        let mut bytes = Vec::with_capacity(ENVELOPE_HEADER_SIZE + payload.len());
+        bytes.push(ENVELOPE_VERSION);
+        bytes.push(tag);
+        bytes.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        bytes.extend_from_slice(&payload);
 
         Ok(bytes)
     }
 
+    /// Decodes an enveloped message. Unknown versions and message types are
+    /// reported as such, as they are most likely sent by newer peers.
     pub fn decode(data: &[u8]) -> Result<Self, Error> {
-        let instance: Self = deserialize(&data[..])?;
+        match data.first() {
+            None => return Err(Error::InvalidEnvelope("empty message".into())),
+            Some(&ENVELOPE_VERSION) => {}
+            Some(version) => return Err(Error::UnsupportedEnvelopeVersion(*version)),
+        }
+
+        if data.len() < ENVELOPE_HEADER_SIZE {
+            return Err(Error::InvalidEnvelope("truncated header".into()));
+        }
+
+        let tag = data[1];
+        let mut length = [0u8; 4];
+        length.copy_from_slice(&data[2..ENVELOPE_HEADER_SIZE]);
+        let length = u32::from_be_bytes(length) as usize;
+
+        if length > MAX_MESSAGE_SIZE {
+            return Err(Error::MessageTooLarge(length));
+        }
+
+        let payload = &data[ENVELOPE_HEADER_SIZE..];
+        if payload.len() != length {
+            return Err(Error::InvalidEnvelope(format!(
+                "payload of {} bytes, expected {}",
+                payload.len(),
+                length
+            )));
+        }
+
+        match tag {
+            BLOCK_MESSAGE => Ok(GossipsubMessage::Block(deserialize(payload)?)),
+            VOTE_MESSAGE => Ok(GossipsubMessage::Vote(deserialize(payload)?)),
+            tag => Err(Error::UnknownMessageType(tag)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libp2p::identity::ed25519::Keypair;
+
+    use crate::spec::ChainSpec;
+
+    #[test]
+    fn test_status_compatibility() {
+        let (genesis_hash, _, _) = Block::genesis_block();
+        let spec_digest = ChainSpec::default().digest();
+        let status = Status::new(DEFAULT_NETWORK, spec_digest, genesis_hash, 0, 0);
+        assert_eq!(
+            status.check_compatible(DEFAULT_NETWORK, &spec_digest),
+            Ok(())
+        );
+
+        let mut other = status.clone();
+        other.protocol_version += 1;
+        assert_eq!(
+            other.check_compatible(DEFAULT_NETWORK, &spec_digest),
+            Err(Incompatibility::ProtocolVersion(PROTOCOL_VERSION + 1))
+        );
+
+        assert_eq!(
+            status.check_compatible("test", &spec_digest),
+            Err(Incompatibility::Network(DEFAULT_NETWORK.to_string()))
+        );
+
+        let mut other = status.clone();
+        other.genesis_hash = BlockHash::digest(b"other genesis");
+        assert_eq!(
+            other.check_compatible(DEFAULT_NETWORK, &spec_digest),
+            Err(Incompatibility::GenesisHash(other.genesis_hash))
+        );
+
+        let mut other = status.clone();
+        other.dictionary_digest = BlockHash::digest(b"other dictionary");
+        assert_eq!(
+            other.check_compatible(DEFAULT_NETWORK, &spec_digest),
+            Err(Incompatibility::Dictionary(other.dictionary_digest))
+        );
+
+        let mut other = status;
+        other.spec_digest = BlockHash::digest(b"other spec");
+        assert_eq!(
+            other.check_compatible(DEFAULT_NETWORK, &spec_digest),
+            Err(Incompatibility::ChainSpec(other.spec_digest))
+        );
+    }
+
+    fn signed_block() -> SignedBlock {
+        let wordlist = vec!["and", "for", "that", "this"];
+        let proposer = Keypair::generate();
+        let (parent_hash, _, _) = Block::genesis_block();
+
+        Block::new(
+            wordlist.into_iter().map(String::from).collect(),
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        )
+        .unwrap()
+        .sign(&proposer)
+    }
+
+    fn block_hash(message: GossipsubMessage) -> BlockHash {
+        match message {
+            GossipsubMessage::Block(signed_block) => signed_block.message.hash,
+            GossipsubMessage::Vote(vote) => vote.hash,
+        }
+    }
+
+    #[test]
+    fn test_envelope() {
+        let signed_block = signed_block();
+        let hash = signed_block.message.hash;
+
+        let bytes = GossipsubMessage::Block(signed_block).encode().unwrap();
+        assert_eq!(bytes[0], ENVELOPE_VERSION);
+        assert_eq!(block_hash(GossipsubMessage::decode(&bytes).unwrap()), hash);
+
+        let mut unknown = bytes.clone();
+        unknown[1] = 42;
+        assert_eq!(
+            GossipsubMessage::decode(&unknown).unwrap_err(),
+            Error::UnknownMessageType(42)
+        );
+
+        let mut newer = bytes.clone();
+        newer[0] = ENVELOPE_VERSION + 1;
+        assert_eq!(
+            GossipsubMessage::decode(&newer).unwrap_err(),
+            Error::UnsupportedEnvelopeVersion(ENVELOPE_VERSION + 1)
+        );
+
+        let mut oversized = bytes.clone();
+        oversized[2..ENVELOPE_HEADER_SIZE].copy_from_slice(&(1u32 << 20).to_be_bytes());
+        assert_eq!(
+            GossipsubMessage::decode(&oversized).unwrap_err(),
+            Error::MessageTooLarge(1 << 20)
+        );
+
+        assert!(GossipsubMessage::decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(GossipsubMessage::decode(&[]).is_err());
+    }
 
-        Ok(instance)
+    #[test]
+    fn test_blocks_topic() {
+        assert_eq!(
+            blocks_topic("main").no_hash().as_str(),
+            "/monkey/main/5/blocks"
+        );
+        assert_ne!(
+            blocks_topic("main").no_hash(),
+            blocks_topic("test").no_hash()
+        );
     }
 }
@@ -0,0 +1,182 @@
+use std::collections::{HashSet, VecDeque};
+use std::task::{Context, Poll};
+
+use libp2p::{
+    core::connection::ConnectionId,
+    gossipsub::{
+        protocol::GossipsubControlAction, Gossipsub, GossipsubEvent, GossipsubRpc, MessageId, Topic,
+    },
+    swarm::{NetworkBehaviour, NetworkBehaviourAction, PollParameters, ProtocolsHandler},
+    Multiaddr, PeerId,
+};
+
+// Rejected ids only need to outlive the Gossipsub message cache
+const MAX_REJECTED: usize = 1024;
+
+/// Gossipsub with manual propagation still caches every received message
+/// before it is validated, and advertises its cache through IHAVE and serves
+/// it on IWANT. This wraps it to keep rejected messages out of both.
+pub struct ValidatedGossipsub {
+    gossipsub: Gossipsub,
+    rejected: HashSet<MessageId>,
+
+    // Rejection order, the oldest ids are forgotten first
+    rejected_order: VecDeque<MessageId>,
+}
+
+impl ValidatedGossipsub {
+    pub fn new(gossipsub: Gossipsub) -> Self {
+        ValidatedGossipsub {
+            gossipsub: gossipsub,
+            rejected: HashSet::new(),
+            rejected_order: VecDeque::new(),
+        }
+    }
+
+    pub fn propagate_message(&mut self, message_id: &MessageId, source: &PeerId) -> bool {
+        self.gossipsub.propagate_message(message_id, source)
+    }
+
+    pub fn subscribe(&mut self, topic: Topic) -> bool {
+        self.gossipsub.subscribe(topic)
+    }
+
+    pub fn publish(&mut self, topic: &Topic, data: impl Into<Vec<u8>>) {
+        self.gossipsub.publish(topic, data)
+    }
+
+    /// Stops advertising and serving a message that failed validation.
+    pub fn reject(&mut self, message_id: MessageId) {
+        if !self.rejected.insert(message_id.clone()) {
+            return;
+        }
+
+        self.rejected_order.push_back(message_id);
+        if self.rejected_order.len() > MAX_REJECTED {
+            let oldest = self.rejected_order.pop_front().unwrap();
+            self.rejected.remove(&oldest);
+        }
+    }
+
+    /// Drops rejected ids from IHAVE announcements, returns `false` if
+    /// nothing is left to send.
+    fn filter_outbound(&self, rpc: &mut GossipsubRpc) -> bool {
+        for control_msg in rpc.control_msgs.iter_mut() {
+            if let GossipsubControlAction::IHave { message_ids, .. } = control_msg {
+                message_ids.retain(|id| !self.rejected.contains(id));
+            }
+        }
+        rpc.control_msgs.retain(|control_msg| match control_msg {
+            GossipsubControlAction::IHave { message_ids, .. } => !message_ids.is_empty(),
+            _ => true,
+        });
+
+        !rpc.messages.is_empty() || !rpc.subscriptions.is_empty() || !rpc.control_msgs.is_empty()
+    }
+}
+
+impl NetworkBehaviour for ValidatedGossipsub {
+    type ProtocolsHandler = <Gossipsub as NetworkBehaviour>::ProtocolsHandler;
+    type OutEvent = GossipsubEvent;
+
+    fn new_handler(&mut self) -> Self::ProtocolsHandler {
+        self.gossipsub.new_handler()
+    }
+
+    fn addresses_of_peer(&mut self, peer_id: &PeerId) -> Vec<Multiaddr> {
+        self.gossipsub.addresses_of_peer(peer_id)
+    }
+
+    fn inject_connected(&mut self, peer_id: &PeerId) {
+        self.gossipsub.inject_connected(peer_id)
+    }
+
+    fn inject_disconnected(&mut self, peer_id: &PeerId) {
+        self.gossipsub.inject_disconnected(peer_id)
+    }
+
+    fn inject_event(&mut self, peer_id: PeerId, connection: ConnectionId, mut rpc: GossipsubRpc) {
+        // Rejected messages are never served, whoever asks for them
+        for control_msg in rpc.control_msgs.iter_mut() {
+            if let GossipsubControlAction::IWant { message_ids } = control_msg {
+                message_ids.retain(|id| !self.rejected.contains(id));
+            }
+        }
+
+        self.gossipsub.inject_event(peer_id, connection, rpc)
+    }
+
+    fn poll(
+        &mut self,
+        cx: &mut Context,
+        params: &mut impl PollParameters,
+    ) -> Poll<
+        NetworkBehaviourAction<
+            <Self::ProtocolsHandler as ProtocolsHandler>::InEvent,
+            Self::OutEvent,
+        >,
+    > {
+        loop {
+            match self.gossipsub.poll(cx, params) {
+                Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                    peer_id,
+                    handler,
+                    mut event,
+                }) => {
+                    if self.filter_outbound(&mut event) {
+                        return Poll::Ready(NetworkBehaviourAction::NotifyHandler {
+                            peer_id,
+                            handler,
+                            event,
+                        });
+                    }
+                }
+                poll => return poll,
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use libp2p::gossipsub::{GossipsubConfig, TopicHash};
+
+    #[test]
+    fn test_filter_rejected_ihave() {
+        let mut gossipsub =
+            ValidatedGossipsub::new(Gossipsub::new(PeerId::random(), GossipsubConfig::default()));
+        let rejected = MessageId("rejected".to_string());
+        let valid = MessageId("valid".to_string());
+        gossipsub.reject(rejected.clone());
+
+        let ihave = |message_ids| GossipsubRpc {
+            messages: Vec::new(),
+            subscriptions: Vec::new(),
+            control_msgs: vec![GossipsubControlAction::IHave {
+                topic_hash: TopicHash::from_raw("blocks"),
+                message_ids: message_ids,
+            }],
+        };
+
+        let mut rpc = ihave(vec![rejected.clone(), valid.clone()]);
+        assert!(gossipsub.filter_outbound(&mut rpc));
+        assert_eq!(rpc, ihave(vec![valid]));
+
+        let mut rpc = ihave(vec![rejected]);
+        assert!(!gossipsub.filter_outbound(&mut rpc));
+    }
+
+    #[test]
+    fn test_forget_oldest_rejected() {
+        let mut gossipsub =
+            ValidatedGossipsub::new(Gossipsub::new(PeerId::random(), GossipsubConfig::default()));
+
+        for i in 0..=MAX_REJECTED {
+            gossipsub.reject(MessageId(i.to_string()));
+        }
+
+        assert_eq!(gossipsub.rejected.len(), MAX_REJECTED);
+        assert!(!gossipsub.rejected.contains(&MessageId("0".to_string())));
+    }
+}
@@ -1,15 +1,166 @@
 extern crate clap;
 
-#[macro_use]
-extern crate log;
-
+use std::env;
+use std::fs;
 use std::path::Path;
 
-use clap::{App, Arg};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
 use env_logger::{Builder, Env};
+use libp2p::{identity::Keypair, identity::PublicKey, Multiaddr, PeerId};
 use tokio::runtime;
 
-use monkeylib::Service;
+use monkeylib::{
+    authority::AuthorityChange, keystore, spec::ChainSpec, Service, DEFAULT_MAX_FUTURE_DRIFT,
+    DEFAULT_NETWORK,
+};
+
+const PEERS_FILE: &str = "peers";
+const PASSWORD_VAR: &str = "MONKEY_PASSWORD";
+
+fn db_arg() -> Arg<'static, 'static> {
+    Arg::with_name("db")
+        .help("directory name for database")
+        .required(true)
+        .takes_value(true)
+}
+
+fn parse_addrs<'a>(
+    addrs: impl Iterator<Item = &'a str>,
+) -> Result<Vec<Multiaddr>, Box<dyn std::error::Error>> {
+    addrs
+        .map(|addr| {
+            addr.parse()
+                .map_err(|e| format!("Invalid multiaddr {}: {}", addr, e).into())
+        })
+        .collect()
+}
+
+/// Network ids end up in gossip topic names, so they are kept to
+/// characters that cannot be confused with the topic separators.
+fn validate_network(network: String) -> Result<(), String> {
+    match !network.is_empty()
+        && network
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        true => Ok(()),
+        false => Err(format!("Invalid network id {:?}", network)),
+    }
+}
+
+/// Reads the multiaddrs listed in the `peers` file of the database
+/// directory, one per line. Empty lines and lines starting with `#` are
+/// skipped.
+fn read_peers_file(path: &Path) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let peers_file = path.join(PEERS_FILE);
+    if !peers_file.exists() {
+        return Ok(Vec::new());
+    }
+
+    let peers = fs::read_to_string(&peers_file)?
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(String::from)
+        .collect();
+
+    Ok(peers)
+}
+
+/// Password of the node key in `path`, if it needs one. It is taken from
+/// `MONKEY_PASSWORD` or asked for on the terminal, and never passed as an
+/// argument where it would show up in the process list.
+fn key_password(path: &Path, encrypt: bool) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    if let Ok(password) = env::var(PASSWORD_VAR) {
+        return Ok(Some(password));
+    }
+
+    if encrypt {
+        let password = rpassword::read_password_from_tty(Some("New node key password: "))?;
+        if rpassword::read_password_from_tty(Some("Repeat the password: "))? != password {
+            return Err("Passwords do not match".into());
+        }
+
+        return Ok(Some(password));
+    }
+
+    match keystore::is_encrypted(path) {
+        true => Ok(Some(rpassword::read_password_from_tty(Some(
+            "Node key password: ",
+        ))?)),
+        false => Ok(None),
+    }
+}
+
+fn show_key(keypair: &Keypair) {
+    let public_key = keypair.public();
+
+    println!("Peer id:    {}", PeerId::from(public_key.clone()));
+    if let PublicKey::Ed25519(pk) = public_key {
+        let hex: String = pk.encode().iter().map(|b| format!("{:02x}", b)).collect();
+        println!("Public key: {}", hex);
+    }
+}
+
+fn key_command(
+    matches: &ArgMatches,
+    path: &Path,
+    password: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keypair = match matches.subcommand() {
+        ("keygen", _) => keystore::generate(path, password),
+        _ => keystore::load(path, password),
+    };
+
+    match keypair {
+        Ok(keypair) => show_key(&keypair),
+        Err(e) => return Err(format!("Failed to access node key in {:?}: {:?}", path, e).into()),
+    }
+
+    Ok(())
+}
+
+/// Adds the signature of the node key to an authority change file, so that
+/// the change can be passed around until a majority of authorities signed.
+fn sign_authority_change(
+    path: &Path,
+    change_path: &Path,
+    password: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keypair = match keystore::load(path, password) {
+        Ok(Keypair::Ed25519(keypair)) => keypair,
+        Ok(_) => panic!("Only Ed25519 scheme is supported"),
+        Err(e) => return Err(format!("Failed to load node key from {:?}: {:?}", path, e).into()),
+    };
+
+    let mut change = match AuthorityChange::load(change_path) {
+        Ok(change) => change,
+        Err(e) => {
+            return Err(format!(
+                "Failed to load authority change from {:?}: {:?}",
+                change_path, e
+            )
+            .into())
+        }
+    };
+
+    change.sign(&keypair);
+    if let Err(e) = change.save(change_path) {
+        return Err(format!(
+            "Failed to save authority change to {:?}: {:?}",
+            change_path, e
+        )
+        .into());
+    }
+
+    println!(
+        "Signed authority change {}, {} signatures",
+        change.sequence,
+        change.signatures.len()
+    );
+
+    Ok(())
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     Builder::from_env(Env::default()).init();
@@ -18,43 +169,170 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
         .version("1.0")
         .author("Rohit Narurkar <rohit.narurkar@protonmail.com>")
         .about("Monkey is a command-line P2P toy blockchain")
+        .setting(AppSettings::SubcommandsNegateReqs)
+        .setting(AppSettings::ArgsNegateSubcommands)
+        .arg(db_arg())
         .arg(
-            Arg::with_name("db")
-                .help("directory name for database")
-                .required(true)
+            Arg::with_name("peer")
+                .long("peer")
+                .help("multiaddr of a peer to connect to")
+                .multiple(true)
+                .number_of_values(1)
                 .takes_value(true),
         )
         .arg(
-            Arg::with_name("peer")
-                .help("multiaddr of a peer")
+            Arg::with_name("bootnode")
+                .long("bootnode")
+                .help("multiaddr of a bootnode, ending in /p2p/<peer id>")
+                .multiple(true)
+                .number_of_values(1)
                 .takes_value(true),
         )
+        .arg(
+            Arg::with_name("network")
+                .long("network")
+                .help("id of the network to join, peers on other networks are ignored")
+                .default_value(DEFAULT_NETWORK)
+                .validator(validate_network)
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("chain-spec")
+                .long("chain-spec")
+                .help("JSON file with the protocol rules, the built-in rules are used otherwise")
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("max-future-drift")
+                .long("max-future-drift")
+                .help("seconds a block may be timestamped ahead of the local clock, 15 by default")
+                .validator(|drift| drift.parse::<u64>().map(|_| ()).map_err(|e| e.to_string()))
+                .takes_value(true),
+        )
+        .arg(
+            Arg::with_name("no-mdns")
+                .long("no-mdns")
+                .help("disables peer discovery on the local network"),
+        )
+        .subcommand(
+            SubCommand::with_name("keygen")
+                .about("Generates the node key of a database")
+                .arg(db_arg())
+                .arg(Arg::with_name("encrypt").long("encrypt").help(
+                    "encrypts the key with a password, asked for unless MONKEY_PASSWORD is set",
+                )),
+        )
+        .subcommand(
+            SubCommand::with_name("key")
+                .about("Inspects the node key of a database")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("show")
+                        .about("Shows the peer id and public key")
+                        .arg(db_arg()),
+                ),
+        )
+        .subcommand(
+            SubCommand::with_name("authorities")
+                .about("Manages changes of the proof-of-authority set")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("sign")
+                        .about("Signs an authority change file with the node key")
+                        .arg(db_arg())
+                        .arg(
+                            Arg::with_name("change")
+                                .help("JSON file with the authority change")
+                                .required(true)
+                                .takes_value(true),
+                        ),
+                ),
+        )
         .get_matches();
 
-    let to_dial = match matches.value_of("peer") {
-        Some(peer) => match peer.parse() {
-            Ok(to_dial) => Some(to_dial),
-            Err(err) => {
-                error!("Failed to parse peer to dial: {:?}", err);
-                None
-            }
-        },
+    let key_matches = match matches.subcommand() {
+        ("keygen", Some(sub_matches)) => Some(sub_matches),
+        ("key", Some(sub_matches)) => sub_matches.subcommand_matches("show"),
         _ => None,
     };
+    if let Some(sub_matches) = key_matches {
+        let db_name = sub_matches.value_of("db").unwrap();
+        let path = Path::new(".data").join(".blockchain").join(db_name);
+        let password = key_password(&path, sub_matches.is_present("encrypt"))?;
+
+        return key_command(&matches, &path, password.as_deref());
+    }
+
+    if let Some(sub_matches) = matches
+        .subcommand_matches("authorities")
+        .and_then(|sub_matches| sub_matches.subcommand_matches("sign"))
+    {
+        let db_name = sub_matches.value_of("db").unwrap();
+        let path = Path::new(".data").join(".blockchain").join(db_name);
+        let change_path = Path::new(sub_matches.value_of("change").unwrap());
+        let password = key_password(&path, false)?;
+
+        return sign_authority_change(&path, change_path, password.as_deref());
+    }
 
     let db_name = matches.value_of("db").unwrap();
+    let path = Path::new(".data").join(".blockchain").join(db_name);
+
+    let mut peers: Vec<String> = matches
+        .values_of("peer")
+        .into_iter()
+        .flatten()
+        .map(String::from)
+        .collect();
+    peers.extend(read_peers_file(&path)?);
+    let to_dial = parse_addrs(peers.iter().map(String::as_str))?;
+
+    let bootnodes = parse_addrs(matches.values_of("bootnode").into_iter().flatten())?;
+
+    let network = matches.value_of("network").unwrap();
+    let spec = match matches.value_of("chain-spec") {
+        Some(spec_path) => match ChainSpec::load(Path::new(spec_path)) {
+            Ok(spec) => spec,
+            Err(e) => {
+                return Err(
+                    format!("Failed to load chain spec from {:?}: {:?}", spec_path, e).into(),
+                )
+            }
+        },
+        None => ChainSpec::default(),
+    };
+    let enable_mdns = !matches.is_present("no-mdns");
+    let max_future_drift = matches
+        .value_of("max-future-drift")
+        .map_or(DEFAULT_MAX_FUTURE_DRIFT, |drift| drift.parse().unwrap());
 
     let rt = runtime::Builder::new()
         .threaded_scheduler()
         .core_threads(4)
+        .enable_time()
         .build()
         .unwrap();
 
     let rt_handle = rt.handle();
 
-    let path = Path::new(".data").join(".blockchain").join(db_name);
-    let mut service = Service::new(&rt_handle, &path).ok().unwrap();
-    service.start(&rt_handle, to_dial)?;
+    let password = key_password(&path, false)?;
+    let keypair = match keystore::load_or_generate(&path, password.as_deref()) {
+        Ok(keypair) => keypair,
+        Err(e) => return Err(format!("Failed to load node key from {:?}: {:?}", path, e).into()),
+    };
+
+    let mut service = Service::new(
+        &rt_handle,
+        &path,
+        keypair,
+        network,
+        spec,
+        enable_mdns,
+        max_future_drift,
+    )
+    .ok()
+    .unwrap();
+    service.start(&rt_handle, to_dial, bootnodes)?;
 
     Ok(())
 }
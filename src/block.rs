@@ -1,111 +1,384 @@
-use std::collections::{hash_map::DefaultHasher, BTreeSet};
-use std::hash::{Hash, Hasher};
-use std::iter::FromIterator;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, Ordering};
 
-use bincode::serialize;
 use chrono::{DateTime, Utc};
 use libp2p::identity::ed25519::{Keypair, PublicKey};
 use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
 
-use crate::dictionary::DICTIONARY;
+use crate::authority::AuthorityChange;
 use crate::errors::Error;
+use crate::merkle::{self, MerkleProof};
+use crate::spec::ChainSpec;
 
-const BLOCK_WORDSET_LENGTH: usize = 4usize;
 const GENESIS_BLOCK_DATA: u16 = 1337;
 
 lazy_static! {
-    static ref GENESIS_BLOCK_HASH: u64 = {
-        let mut hasher = DefaultHasher::new();
-        hasher.write_u16(GENESIS_BLOCK_DATA);
+    static ref GENESIS_BLOCK_HASH: BlockHash = BlockHash::digest(&GENESIS_BLOCK_DATA.to_be_bytes());
+}
+
+/// SHA-256 digest identifying a block.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BlockHash([u8; 32]);
+
+impl BlockHash {
+    pub fn digest(data: &[u8]) -> Self {
+        let mut hash = [0u8; 32];
+        hash.copy_from_slice(&Sha256::digest(data));
+
+        BlockHash(hash)
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.0
+    }
+
+    /// Number of leading zero bits, the proof of work behind the hash.
+    pub fn leading_zeros(&self) -> u32 {
+        let mut zeros = 0;
+        for byte in self.0.iter() {
+            zeros += byte.leading_zeros();
+
+            if *byte != 0 {
+                break;
+            }
+        }
 
-        hasher.finish()
-    };
+        zeros
+    }
+}
+
+impl fmt::Display for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for byte in self.0.iter() {
+            write!(f, "{:02x}", byte)?;
+        }
+
+        Ok(())
+    }
 }
 
+impl fmt::Debug for BlockHash {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Display::fmt(self, f)
+    }
+}
+
+/// A protocol rule every block body has to satisfy, given its distinct
+/// words in the order they were submitted.
+pub trait Rule {
+    fn check(&self, words: &[String], dictionary: &BTreeSet<String>) -> Result<(), Error>;
+}
+
+/// The wordset rules a chain spec can be made of.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum WordRule {
+    /// Exactly this many distinct words.
+    Length {
+        words: usize,
+    },
+    MinLength {
+        words: usize,
+    },
+    MaxLength {
+        words: usize,
+    },
+    /// Every word is in the dictionary.
+    Dictionary,
+    /// Every word has between `min` and `max` letters.
+    LettersPerWord {
+        min: usize,
+        max: usize,
+    },
+    /// Every word starts with `letter`.
+    StartsWith {
+        letter: char,
+    },
+    /// No letter appears in more than one word.
+    NoSharedLetters,
+    /// Words are submitted in alphabetical order, as in `ant bee dog`.
+    Alphabetical,
+}
+
+impl Rule for WordRule {
+    fn check(&self, wordset: &[String], dictionary: &BTreeSet<String>) -> Result<(), Error> {
+        let valid = match self {
+            WordRule::Length { words } => wordset.len() == *words,
+            WordRule::MinLength { words } => wordset.len() >= *words,
+            WordRule::MaxLength { words } => wordset.len() <= *words,
+            WordRule::Dictionary => wordset.iter().all(|word| dictionary.contains(word)),
+            WordRule::LettersPerWord { min, max } => wordset
+                .iter()
+                .all(|word| (*min..=*max).contains(&word.chars().count())),
+            WordRule::StartsWith { letter } => wordset.iter().all(|word| word.starts_with(*letter)),
+            WordRule::NoSharedLetters => {
+                let mut seen = BTreeSet::new();
+
+                wordset.iter().all(|word| {
+                    let letters: BTreeSet<char> = word.chars().collect();
+                    let shared = !seen.is_disjoint(&letters);
+                    seen.extend(letters);
+
+                    !shared
+                })
+            }
+            WordRule::Alphabetical => wordset.windows(2).all(|pair| pair[0] <= pair[1]),
+        };
+
+        match valid {
+            true => Ok(()),
+            false => Err(match self {
+                WordRule::Length { .. } => Error::InvalidWordsetLength,
+                WordRule::MinLength { .. } => Error::WordsetTooShort,
+                WordRule::MaxLength { .. } => Error::WordsetTooLong,
+                WordRule::Dictionary => Error::InvalidWordset,
+                WordRule::LettersPerWord { .. } => Error::InvalidWordLength,
+                WordRule::StartsWith { .. } => Error::InvalidInitial,
+                WordRule::NoSharedLetters => Error::SharedLetters,
+                WordRule::Alphabetical => Error::NotAlphabetical,
+            }),
+        }
+    }
+}
+
+impl fmt::Display for WordRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            WordRule::Length { words } => write!(f, "exactly {} words", words),
+            WordRule::MinLength { words } => write!(f, "at least {} words", words),
+            WordRule::MaxLength { words } => write!(f, "at most {} words", words),
+            WordRule::Dictionary => write!(f, "every word in the dictionary"),
+            WordRule::LettersPerWord { min, max } => {
+                write!(f, "{} to {} letters per word", min, max)
+            }
+            WordRule::StartsWith { letter } => write!(f, "every word starting with '{}'", letter),
+            WordRule::NoSharedLetters => write!(f, "no letter in more than one word"),
+            WordRule::Alphabetical => write!(f, "in alphabetical order"),
+        }
+    }
+}
+
+/// Scrabble value of a letter, so that rare letters are worth more.
+fn letter_value(letter: char) -> u64 {
+    match letter.to_ascii_lowercase() {
+        'a' | 'e' | 'i' | 'l' | 'n' | 'o' | 'r' | 's' | 't' | 'u' => 1,
+        'd' | 'g' => 2,
+        'b' | 'c' | 'm' | 'p' => 3,
+        'f' | 'h' | 'v' | 'w' | 'y' => 4,
+        'k' => 5,
+        'j' | 'x' => 8,
+        'q' | 'z' => 10,
+        _ => 0,
+    }
+}
+
+/// The distinct words of a block, in the order they were submitted. The
+/// body root commits to that order, so the same words in a different order
+/// make a different block.
 #[derive(Clone, Debug, Hash, Deserialize, Serialize)]
 pub struct BlockBody {
-    wordset: BTreeSet<String>,
+    wordset: Vec<String>,
 }
 
 impl BlockBody {
-    pub fn new(wordlist: Vec<String>) -> Result<BlockBody, Error> {
-        let wordset = BTreeSet::from_iter(wordlist.into_iter());
+    pub fn new<R: Rule>(
+        wordlist: Vec<String>,
+        rules: &[R],
+        dictionary: &BTreeSet<String>,
+    ) -> Result<BlockBody, Error> {
+        let mut seen = BTreeSet::new();
+        let wordset = wordlist
+            .into_iter()
+            .filter(|word| seen.insert(word.clone()))
+            .collect();
 
         let body = BlockBody { wordset: wordset };
+        body.validate(rules, dictionary)?;
 
-        body.validate_length().and_then(Self::validate_subset)
+        Ok(body)
     }
 
-    #[allow(dead_code)]
-    pub fn validate(self) -> Result<Self, Error> {
-        self.validate_length().and_then(Self::validate_subset)
+    /// Checks the rules in order, failing with the error of the first one
+    /// that is broken.
+    pub fn validate<R: Rule>(
+        &self,
+        rules: &[R],
+        dictionary: &BTreeSet<String>,
+    ) -> Result<(), Error> {
+        let mut seen = BTreeSet::new();
+        if let Some(word) = self.wordset.iter().find(|word| !seen.insert(*word)) {
+            return Err(Error::DuplicateWord(word.clone()));
+        }
+
+        rules
+            .iter()
+            .try_for_each(|rule| rule.check(&self.wordset, dictionary))
     }
 
-    pub fn validate_subset(self) -> Result<BlockBody, Error> {
-        match self.wordset.is_subset(&DICTIONARY) {
-            true => Ok(self),
-            false => Err(Error::InvalidWordset),
-        }
+    /// Sum of the letter values of all words, long words with rare letters
+    /// weigh the most.
+    pub fn score(&self) -> u64 {
+        self.wordset
+            .iter()
+            .flat_map(|word| word.chars())
+            .map(letter_value)
+            .sum()
     }
 
-    pub fn validate_length(self) -> Result<BlockBody, Error> {
-        match self.wordset.iter().len() {
-            BLOCK_WORDSET_LENGTH => Ok(self),
-            _ => Err(Error::InvalidWordsetLength),
-        }
+    fn leaves(&self) -> Vec<BlockHash> {
+        self.wordset
+            .iter()
+            .map(|word| merkle::leaf_hash(word.as_bytes()))
+            .collect()
+    }
+
+    /// Merkle root over the words in their submitted order.
+    pub fn root(&self) -> BlockHash {
+        merkle::root(&self.leaves())
+    }
+
+    /// Builds a proof that `word` is part of this body, to be checked
+    /// against the `body_root` of the block header.
+    pub fn prove(&self, word: &str) -> Option<MerkleProof> {
+        let index = self.wordset.iter().position(|w| w == word)?;
+
+        MerkleProof::new(&self.leaves(), index)
     }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
-pub struct Block {
-    body: BlockBody,
-    proposer: [u8; 32],
-    pub hash: u64,
-    pub parent_hash: u64,
-    timestamp: DateTime<Utc>,
+pub struct BlockHeader {
+    pub parent_hash: BlockHash,
+    pub height: u64,
+    pub body_root: BlockHash,
+    pub proposer: [u8; 32],
+    pub timestamp: DateTime<Utc>,
+    pub nonce: u64,
+    pub authority_change: Option<AuthorityChange>,
 }
 
-impl Hash for Block {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        self.body.hash(state);
+impl BlockHeader {
+    /// Hashes a fixed-layout encoding of the header fields, so that the
+    /// result does not depend on the serializer or toolchain in use.
+    pub fn hash(&self) -> BlockHash {
+        let mut encoded = Vec::with_capacity(124);
+        encoded.extend_from_slice(self.body_root.as_bytes());
+        encoded.extend_from_slice(&self.proposer);
+        encoded.extend_from_slice(self.parent_hash.as_bytes());
+        encoded.extend_from_slice(&self.height.to_be_bytes());
+        encoded.extend_from_slice(&self.timestamp.timestamp().to_be_bytes());
+        encoded.extend_from_slice(&self.timestamp.timestamp_subsec_nanos().to_be_bytes());
+        encoded.extend_from_slice(&self.nonce.to_be_bytes());
+        if let Some(change) = &self.authority_change {
+            encoded.extend_from_slice(change.digest().as_bytes());
+        }
+
+        BlockHash::digest(&encoded)
     }
+
+    /// Checks an inclusion proof for `word` against the body root.
+    pub fn verify_word(&self, word: &str, proof: &MerkleProof) -> bool {
+        proof.verify(&self.body_root, &merkle::leaf_hash(word.as_bytes()))
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Block {
+    pub header: BlockHeader,
+    body: BlockBody,
+    pub hash: BlockHash,
 }
 
 impl Block {
+    /// Builds a block at `height`, failing if the words break the rules of
+    /// the epoch in force at that height.
     pub fn new(
         wordlist: Vec<String>,
         proposer: PublicKey,
-        parent_hash: u64,
+        parent_hash: BlockHash,
+        height: u64,
+        spec: &ChainSpec,
     ) -> Result<Block, Error> {
-        let body = BlockBody::new(wordlist)?;
+        let body = BlockBody::new(wordlist, spec.rules_at(height), spec.dictionary_at(height))?;
 
-        let mut block = Block {
-            body: body,
-            proposer: proposer.encode(),
+        let header = BlockHeader {
             parent_hash: parent_hash,
+            height: height,
+            body_root: body.root(),
+            proposer: proposer.encode(),
             timestamp: Utc::now(),
-            hash: 0,
+            nonce: 0,
+            authority_change: None,
         };
 
-        let mut hasher = DefaultHasher::new();
-        block.hash(&mut hasher);
-        block.hash = hasher.finish();
-
-        Ok(block)
+        Ok(Block {
+            hash: header.hash(),
+            header: header,
+            body: body,
+        })
     }
 
-    pub fn validate(self) -> Result<Self, Error> {
+    pub fn validate(self, spec: &ChainSpec) -> Result<Self, Error> {
+        if self.hash != self.header.hash() {
+            return Err(Error::InvalidBlockHash);
+        }
+
+        if self.header.body_root != self.body.root() {
+            return Err(Error::InvalidBodyRoot);
+        }
+
+        let height = self.header.height;
         self.body
-            .clone()
-            .validate_length()
-            .and_then(BlockBody::validate_subset)
-            .map(|_| self)
+            .validate(spec.rules_at(height), spec.dictionary_at(height))?;
+
+        Ok(self)
     }
 
-    pub fn sign(self, keypair: &Keypair) -> SignedBlock {
-        let message = serialize(&self).unwrap();
+    pub fn words(&self) -> &[String] {
+        &self.body.wordset
+    }
+
+    /// Builds a proof that `word` is part of the block, which can be
+    /// handed to anyone holding the header.
+    pub fn prove_word(&self, word: &str) -> Option<MerkleProof> {
+        self.body.prove(word)
+    }
+
+    /// Attaches a change of the proof-of-authority set to the block.
+    pub fn with_authority_change(mut self, change: AuthorityChange) -> Self {
+        self.header.authority_change = Some(change);
+        self.hash = self.header.hash();
+
+        self
+    }
+
+    /// Searches nonces until the block hash has `difficulty` leading zero
+    /// bits. Gives up and returns false once `cancel` is set.
+    pub fn mine(&mut self, difficulty: u32, cancel: &AtomicBool) -> bool {
+        loop {
+            let hash = self.header.hash();
+            if hash.leading_zeros() >= difficulty {
+                self.hash = hash;
+
+                return true;
+            }
+
+            self.header.nonce = self.header.nonce.wrapping_add(1);
+            if self.header.nonce.is_multiple_of(4096) && cancel.load(Ordering::Relaxed) {
+                return false;
+            }
+        }
+    }
 
-        let signature = keypair.sign(&message);
+    /// Weight the block adds to its chain.
+    pub fn score(&self) -> u64 {
+        self.body.score()
+    }
+
+    pub fn sign(self, keypair: &Keypair) -> SignedBlock {
+        let signature = keypair.sign(self.hash.as_bytes());
 
         SignedBlock {
             message: self,
@@ -113,10 +386,10 @@ impl Block {
         }
     }
 
-    pub fn genesis_block() -> (u64, Vec<u8>, Vec<u8>) {
+    pub fn genesis_block() -> (BlockHash, Vec<u8>, Vec<u8>) {
         (
             *GENESIS_BLOCK_HASH,
-            GENESIS_BLOCK_HASH.to_be_bytes().to_vec(),
+            GENESIS_BLOCK_HASH.as_bytes().to_vec(),
             GENESIS_BLOCK_DATA.to_be_bytes().to_vec(),
         )
     }
@@ -132,10 +405,9 @@ pub struct SignedBlock {
 
 impl SignedBlock {
     pub fn verify_signature(&self) -> bool {
-        match PublicKey::decode(&self.message.proposer) {
+        match PublicKey::decode(&self.message.header.proposer) {
             Ok(public_key) => {
-                let message = serialize(&self.message).unwrap();
-                public_key.verify(&message, &self.signature)
+                public_key.verify(self.message.header.hash().as_bytes(), &self.signature)
             }
             _ => false,
         }
@@ -161,6 +433,14 @@ impl SignedBlock {
 mod tests {
     use super::*;
 
+    use crate::dictionary::DICTIONARY;
+
+    fn check(rule: WordRule, words: &[&str]) -> Result<(), Error> {
+        let words: Vec<String> = words.iter().map(|word| word.to_string()).collect();
+
+        rule.check(&words, &DICTIONARY)
+    }
+
     #[test]
     fn test_new() {
         let wordlist = vec![
@@ -170,11 +450,15 @@ mod tests {
             "this".to_string(),
         ];
         let proposer = Keypair::generate();
-        let mut hasher = DefaultHasher::new();
-        hasher.write_u16(1337);
-        let parent_hash = hasher.finish();
+        let (parent_hash, _, _) = Block::genesis_block();
 
-        let result = Block::new(wordlist, proposer.public(), parent_hash);
+        let result = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        );
 
         assert!(result.is_ok());
     }
@@ -188,11 +472,15 @@ mod tests {
             "notaword".to_string(),
         ];
         let proposer = Keypair::generate();
-        let mut hasher = DefaultHasher::new();
-        hasher.write_u16(1337);
-        let parent_hash = hasher.finish();
+        let (parent_hash, _, _) = Block::genesis_block();
 
-        let result = Block::new(wordlist, proposer.public(), parent_hash);
+        let result = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        );
 
         assert!(result.is_err());
         assert_eq!(result.err().unwrap(), Error::InvalidWordset);
@@ -202,16 +490,89 @@ mod tests {
     fn test_invalid_wordset_length() {
         let wordlist = vec!["and".to_string(), "for".to_string(), "that".to_string()];
         let proposer = Keypair::generate();
-        let mut hasher = DefaultHasher::new();
-        hasher.write_u16(1337);
-        let parent_hash = hasher.finish();
+        let (parent_hash, _, _) = Block::genesis_block();
 
-        let result = Block::new(wordlist, proposer.public(), parent_hash);
+        let result = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        );
 
         assert!(result.is_err());
         assert_eq!(result.err().unwrap(), Error::InvalidWordsetLength);
     }
 
+    #[test]
+    fn test_word_order() {
+        let proposer = Keypair::generate();
+        let (parent_hash, _, _) = Block::genesis_block();
+        let block = |words: [&str; 4]| {
+            let wordlist = words.iter().map(|w| w.to_string()).collect();
+
+            Block::new(
+                wordlist,
+                proposer.public(),
+                parent_hash,
+                1,
+                &ChainSpec::default(),
+            )
+            .unwrap()
+        };
+
+        let a = block(["and", "for", "that", "this"]);
+        let mut b = block(["this", "that", "for", "and"]);
+        b.header.timestamp = a.header.timestamp;
+        b.hash = b.header.hash();
+
+        assert_eq!(a.score(), b.score());
+        assert_ne!(a.header.body_root, b.header.body_root);
+        assert_ne!(a.hash, b.hash);
+    }
+
+    #[test]
+    fn test_word_rules() {
+        let words = ["ant", "bee", "cat", "dog"];
+
+        assert!(check(WordRule::Length { words: 4 }, &words).is_ok());
+        assert_eq!(
+            check(WordRule::Length { words: 3 }, &words),
+            Err(Error::InvalidWordsetLength)
+        );
+        assert!(check(WordRule::MinLength { words: 4 }, &words).is_ok());
+        assert_eq!(
+            check(WordRule::MinLength { words: 5 }, &words),
+            Err(Error::WordsetTooShort)
+        );
+        assert!(check(WordRule::MaxLength { words: 4 }, &words).is_ok());
+        assert_eq!(
+            check(WordRule::MaxLength { words: 3 }, &words),
+            Err(Error::WordsetTooLong)
+        );
+        assert!(check(WordRule::LettersPerWord { min: 3, max: 3 }, &words).is_ok());
+        assert_eq!(
+            check(WordRule::LettersPerWord { min: 4, max: 8 }, &words),
+            Err(Error::InvalidWordLength)
+        );
+        assert!(check(WordRule::StartsWith { letter: 'b' }, &["bee", "bat"]).is_ok());
+        assert_eq!(
+            check(WordRule::StartsWith { letter: 'b' }, &words),
+            Err(Error::InvalidInitial)
+        );
+        assert!(check(WordRule::NoSharedLetters, &["ant", "box"]).is_ok());
+        assert_eq!(
+            check(WordRule::NoSharedLetters, &words),
+            Err(Error::SharedLetters)
+        );
+        assert!(check(WordRule::Alphabetical, &words).is_ok());
+        assert!(check(WordRule::Alphabetical, &["ant", "bee", "dog"]).is_ok());
+        assert_eq!(
+            check(WordRule::Alphabetical, &["bee", "ant", "dog"]),
+            Err(Error::NotAlphabetical)
+        );
+    }
+
     #[test]
     fn test_sign_verify_block() {
         let wordlist = vec![
@@ -221,11 +582,15 @@ mod tests {
             "this".to_string(),
         ];
         let proposer = Keypair::generate();
-        let mut hasher = DefaultHasher::new();
-        hasher.write_u16(1337);
-        let parent_hash = hasher.finish();
-
-        let result = Block::new(wordlist, proposer.public(), parent_hash);
+        let (parent_hash, _, _) = Block::genesis_block();
+
+        let result = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        );
         let block = result.ok().unwrap();
         let signed_block = block.clone().sign(&proposer);
 
@@ -237,4 +602,152 @@ mod tests {
         };
         assert_eq!(invalid_signed_block.verify_signature(), false)
     }
+
+    #[test]
+    fn test_genesis_hash() {
+        let (genesis_hash, _, _) = Block::genesis_block();
+
+        assert_eq!(
+            genesis_hash.to_string(),
+            "89b3a748a25efb0e61cc2c6266aa383b82ba6aca170de7a63f6c9af901cc5b99"
+        );
+    }
+
+    #[test]
+    fn test_invalid_block_hash() {
+        let wordlist = vec![
+            "and".to_string(),
+            "for".to_string(),
+            "that".to_string(),
+            "this".to_string(),
+        ];
+        let proposer = Keypair::generate();
+        let (parent_hash, _, _) = Block::genesis_block();
+
+        let mut block = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        )
+        .unwrap();
+        assert!(block.clone().validate(&ChainSpec::default()).is_ok());
+
+        block.header.timestamp = block.header.timestamp + chrono::Duration::seconds(1);
+        assert_eq!(
+            block.validate(&ChainSpec::default()).err().unwrap(),
+            Error::InvalidBlockHash
+        );
+    }
+
+    #[test]
+    fn test_invalid_body_root() {
+        let wordlist = vec![
+            "and".to_string(),
+            "for".to_string(),
+            "that".to_string(),
+            "this".to_string(),
+        ];
+        let proposer = Keypair::generate();
+        let (parent_hash, _, _) = Block::genesis_block();
+
+        let mut block = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        )
+        .unwrap();
+        block.body.wordset.retain(|word| word != "this");
+        block.body.wordset.push("with".to_string());
+
+        assert_eq!(
+            block.validate(&ChainSpec::default()).err().unwrap(),
+            Error::InvalidBodyRoot
+        );
+    }
+
+    #[test]
+    fn test_duplicate_words() {
+        let rules = [WordRule::Length { words: 2 }];
+        let body = BlockBody::new(
+            vec!["and".to_string(), "and".to_string()],
+            &rules,
+            &DICTIONARY,
+        );
+        assert_eq!(body.err(), Some(Error::InvalidWordsetLength));
+
+        let body = BlockBody {
+            wordset: vec!["and".to_string(), "and".to_string()],
+        };
+        assert_eq!(
+            body.validate(&rules, &DICTIONARY),
+            Err(Error::DuplicateWord("and".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_score() {
+        let body = |words: &[&str]| BlockBody {
+            wordset: words.iter().map(|word| word.to_string()).collect(),
+        };
+
+        assert_eq!(body(&[]).score(), 0);
+        assert_eq!(body(&["and", "for", "that", "this"]).score(), 4 + 6 + 7 + 7);
+        assert_eq!(body(&["quiz", "jazz"]).score(), 22 + 29);
+        assert!(body(&["quiz"]).score() > body(&["tone"]).score());
+    }
+
+    #[test]
+    fn test_mine() {
+        let wordlist = vec![
+            "and".to_string(),
+            "for".to_string(),
+            "that".to_string(),
+            "this".to_string(),
+        ];
+        let proposer = Keypair::generate();
+        let (parent_hash, _, _) = Block::genesis_block();
+        let spec = ChainSpec::default();
+
+        let mut block = Block::new(wordlist, proposer.public(), parent_hash, 1, &spec).unwrap();
+        assert!(block.mine(8, &AtomicBool::new(false)));
+        assert!(block.hash.leading_zeros() >= 8);
+
+        let block = block.validate(&spec).unwrap();
+        assert_eq!(block.hash, block.header.hash());
+
+        let mut hash = [0u8; 32];
+        hash[2] = 0x10;
+        assert_eq!(BlockHash(hash).leading_zeros(), 19);
+    }
+
+    #[test]
+    fn test_word_inclusion_proof() {
+        let wordlist = vec![
+            "and".to_string(),
+            "for".to_string(),
+            "that".to_string(),
+            "this".to_string(),
+        ];
+        let proposer = Keypair::generate();
+        let (parent_hash, _, _) = Block::genesis_block();
+
+        let block = Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            1,
+            &ChainSpec::default(),
+        )
+        .unwrap();
+        let header = block.clone().sign(&proposer).message.header;
+
+        let proof = block.prove_word("that").unwrap();
+        assert!(header.verify_word("that", &proof));
+        assert!(!header.verify_word("this", &proof));
+        assert!(block.prove_word("with").is_none());
+    }
 }
@@ -0,0 +1,1449 @@
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use bincode::{deserialize, serialize};
+use chrono::{DateTime, Utc};
+
+use crate::authority::AuthorityKey;
+use crate::block::{Block, BlockHash, SignedBlock};
+use crate::errors::Error;
+use crate::finality::{CheckpointVote, FinalityTracker};
+use crate::spec::{ChainSpec, ProofOfAuthority, ProofOfWork, MAX_DIFFICULTY};
+use crate::store::DiscStore;
+
+const HEAD_KEY: &[u8] = b"head";
+const FINALIZED_KEY: &[u8] = b"finalized";
+
+// Votes on checkpoints above the finalized block, and the votes that
+// finalized it
+const VOTES_KEY: &[u8] = b"votes";
+const FINALITY_PROOF_KEY: &[u8] = b"finality-proof";
+const CANONICAL_KEY_PREFIX: u8 = b'c';
+
+// Set once the used word index covers the canonical chain, databases
+// created before the index existed get it rebuilt on startup
+const WORD_INDEX_KEY: &[u8] = b"word-index";
+const WORD_KEY_PREFIX: u8 = b'w';
+
+// Children of every inserted block along with the order they arrived in,
+// numbered across restarts
+const CHILDREN_KEY_PREFIX: u8 = b'k';
+const ARRIVALS_KEY: &[u8] = b"arrivals";
+
+// Number of ancestors whose median time a block has to be after
+const MEDIAN_TIME_BLOCKS: usize = 11;
+
+fn canonical_key(height: u64) -> Vec<u8> {
+    let mut key = vec![CANONICAL_KEY_PREFIX];
+    key.extend_from_slice(&height.to_be_bytes());
+
+    key
+}
+
+fn children_key(hash: &BlockHash) -> Vec<u8> {
+    let mut key = vec![CHILDREN_KEY_PREFIX];
+    key.extend_from_slice(hash.as_bytes());
+
+    key
+}
+
+fn word_key(word: &str) -> Vec<u8> {
+    let mut key = vec![WORD_KEY_PREFIX];
+    key.extend_from_slice(word.as_bytes());
+
+    key
+}
+
+#[derive(Clone, Copy, Debug)]
+struct ChainNode {
+    parent_hash: Option<BlockHash>,
+    height: u64,
+    /// Sum of the block scores from the genesis block on.
+    weight: u64,
+    /// Leading zero bits required of the hashes of children.
+    difficulty: u32,
+    /// None for the genesis block.
+    timestamp: Option<DateTime<Utc>>,
+    proposer: Option<[u8; 32]>,
+    /// Latest block up to this one that changed the authority set, None
+    /// while the set of the chain spec is in force.
+    authority_change: Option<BlockHash>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum ChainEvent {
+    /// The old head is the parent of the new head
+    Extended { head: BlockHash },
+    /// A competing branch became canonical. `retracted` lists the blocks
+    /// removed from the canonical chain starting at the old head, `enacted`
+    /// lists the blocks added to it ending at the new head.
+    Reorg {
+        old_head: BlockHash,
+        new_head: BlockHash,
+        common_ancestor: BlockHash,
+        depth: usize,
+        retracted: Vec<BlockHash>,
+        enacted: Vec<BlockHash>,
+    },
+    /// Two thirds of the validators voted for a checkpoint, which no reorg
+    /// can go past from now on
+    Finalized { hash: BlockHash, height: u64 },
+}
+
+pub struct ChainState {
+    store: Arc<DiscStore>,
+    nodes: HashMap<BlockHash, ChainNode>,
+    head: BlockHash,
+
+    // Latest block agreed on by the validators, the genesis block if there
+    // are none
+    finalized: BlockHash,
+    finality: Option<FinalityTracker>,
+
+    // Number of blocks inserted so far
+    arrivals: u64,
+
+    // Blocks until a used word may appear again, never if unset
+    word_cooldown: Option<u64>,
+    proof_of_work: Option<ProofOfWork>,
+    min_block_interval: Option<u64>,
+    max_consecutive_blocks: Option<u64>,
+    proof_of_authority: Option<ProofOfAuthority>,
+
+    // Authority sets along with their change number, by the block that
+    // changed them
+    authority_sets: HashMap<BlockHash, (u64, Vec<AuthorityKey>)>,
+}
+
+impl ChainState {
+    pub fn new(store: &Arc<DiscStore>, spec: &ChainSpec) -> Result<Self, Error> {
+        let (genesis_hash, _, _) = Block::genesis_block();
+        let proof_of_work = spec.proof_of_work.clone();
+
+        let mut nodes = HashMap::new();
+        nodes.insert(
+            genesis_hash,
+            ChainNode {
+                parent_hash: None,
+                height: 0,
+                weight: 0,
+                difficulty: proof_of_work.as_ref().map_or(0, |pow| pow.difficulty),
+                timestamp: None,
+                proposer: None,
+                authority_change: None,
+            },
+        );
+
+        let head = match store.get(HEAD_KEY) {
+            Some(bytes) => deserialize(&bytes)?,
+            None => {
+                store.put(&canonical_key(0), &serialize(&genesis_hash)?)?;
+
+                genesis_hash
+            }
+        };
+
+        let finalized = match store.get(FINALIZED_KEY) {
+            Some(bytes) => deserialize(&bytes)?,
+            None => genesis_hash,
+        };
+
+        let arrivals = match store.get(ARRIVALS_KEY) {
+            Some(bytes) => deserialize(&bytes)?,
+            None => 0,
+        };
+
+        let mut chain = ChainState {
+            store: Arc::clone(store),
+            nodes,
+            head,
+            finalized,
+            finality: spec.finality.as_ref().map(FinalityTracker::new),
+            arrivals: arrivals,
+            word_cooldown: spec.word_cooldown,
+            proof_of_work,
+            min_block_interval: spec.min_block_interval,
+            max_consecutive_blocks: spec.max_consecutive_blocks,
+            proof_of_authority: spec.proof_of_authority.clone(),
+            authority_sets: HashMap::new(),
+        };
+
+        chain.node(&head)?;
+        chain.node(&finalized)?;
+
+        // Votes counted before a restart
+        if let (Some(finality), Some(bytes)) = (chain.finality.as_mut(), store.get(VOTES_KEY)) {
+            let votes: Vec<CheckpointVote> = deserialize(&bytes)?;
+            for vote in &votes {
+                finality.add_vote(vote)?;
+            }
+        }
+
+        if chain.store.get(WORD_INDEX_KEY).is_none() {
+            chain.rebuild_word_index()?;
+        }
+
+        Ok(chain)
+    }
+
+    pub fn head(&self) -> BlockHash {
+        self.head
+    }
+
+    pub fn head_height(&self) -> u64 {
+        self.nodes[&self.head].height
+    }
+
+    pub fn head_weight(&self) -> u64 {
+        self.nodes[&self.head].weight
+    }
+
+    pub fn finalized_height(&self) -> u64 {
+        self.nodes[&self.finalized].height
+    }
+
+    /// Whether the block at `height` is voted on by the validators.
+    pub fn is_checkpoint(&self, height: u64) -> bool {
+        self.finality
+            .as_ref()
+            .is_some_and(|finality| finality.is_checkpoint(height))
+    }
+
+    /// Returns the height of a known block.
+    pub fn height(&mut self, hash: &BlockHash) -> Result<u64, Error> {
+        self.node(hash).map(|node| node.height)
+    }
+
+    /// Returns the hash of the canonical block at the given height.
+    pub fn canonical_hash(&self, height: u64) -> Option<BlockHash> {
+        let bytes = self.store.get(&canonical_key(height))?;
+
+        deserialize(&bytes).ok()
+    }
+
+    /// Returns up to `count` canonical block hashes ending at the head,
+    /// oldest first. The genesis block is not included.
+    pub fn recent(&self, count: usize) -> Vec<BlockHash> {
+        let head_height = self.head_height();
+        let start = head_height.saturating_sub(count as u64) + 1;
+
+        (start..=head_height)
+            .filter_map(|height| self.canonical_hash(height))
+            .collect()
+    }
+
+    /// Heights of the canonical blocks that used `word`, in ascending order.
+    fn word_heights(&self, word: &str) -> Vec<u64> {
+        self.store
+            .get(&word_key(word))
+            .and_then(|bytes| deserialize(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Height of the latest canonical block that used `word`.
+    pub fn word_height(&self, word: &str) -> Option<u64> {
+        self.word_heights(word).last().copied()
+    }
+
+    /// Checks that none of the words of a block were used by one of its
+    /// ancestors, or by one within the cooldown window if there is one.
+    /// Words used on the canonical chain are looked up in the index, those
+    /// of a competing branch by walking it back to where it forks off.
+    pub fn check_words(&mut self, block: &Block) -> Result<(), Error> {
+        let mut cursor = block.header.parent_hash;
+        let mut fork_height = self.node(&cursor)?.height;
+        let mut fork_words = HashMap::new();
+
+        while self.canonical_hash(fork_height) != Some(cursor) {
+            let signed_block = self.load(&cursor)?;
+            for word in signed_block.message.words() {
+                fork_words.entry(word.clone()).or_insert(fork_height);
+            }
+
+            cursor = signed_block.message.header.parent_hash;
+            fork_height -= 1;
+        }
+
+        for word in block.words() {
+            let last_used = match fork_words.get(word) {
+                Some(height) => Some(*height),
+                None => self
+                    .word_heights(word)
+                    .into_iter()
+                    .take_while(|height| *height <= fork_height)
+                    .last(),
+            };
+
+            match (last_used, self.word_cooldown) {
+                (None, _) => {}
+                (Some(_), None) => return Err(Error::ReusedWord(word.clone())),
+                (Some(height), Some(cooldown)) => {
+                    if block.header.height < height + cooldown {
+                        return Err(Error::WordCooldown(word.clone(), height + cooldown));
+                    }
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Returns the number of leading zero bits required of the hash of a
+    /// child of `parent_hash`, zero without proof of work.
+    pub fn next_difficulty(&mut self, parent_hash: &BlockHash) -> Result<u32, Error> {
+        let parent = self.node(parent_hash)?;
+
+        Ok(parent.difficulty)
+    }
+
+    /// Checks the proof of work of a block against the difficulty in force
+    /// on its branch.
+    pub fn check_work(&mut self, block: &Block) -> Result<(), Error> {
+        let difficulty = self.next_difficulty(&block.header.parent_hash)?;
+
+        match block.hash.leading_zeros() >= difficulty {
+            true => Ok(()),
+            false => Err(Error::InsufficientWork(difficulty)),
+        }
+    }
+
+    /// Checks that a block keeps the minimum interval to its parent, and
+    /// that its proposer did not already produce the maximum number of
+    /// blocks in a row.
+    pub fn check_proposer(&mut self, block: &Block) -> Result<(), Error> {
+        let parent = self.node(&block.header.parent_hash)?;
+
+        if let (Some(min_interval), Some(parent_timestamp)) =
+            (self.min_block_interval, parent.timestamp)
+        {
+            let interval = (block.header.timestamp - parent_timestamp).num_seconds();
+            if interval < min_interval as i64 {
+                return Err(Error::BlockIntervalTooShort(min_interval));
+            }
+        }
+
+        if let Some(max_consecutive) = self.max_consecutive_blocks {
+            let mut consecutive = 0;
+            let mut ancestor = parent;
+            while ancestor.proposer == Some(block.header.proposer) {
+                consecutive += 1;
+                if consecutive >= max_consecutive {
+                    return Err(Error::TooManyConsecutiveBlocks(max_consecutive));
+                }
+
+                ancestor = self.nodes[&ancestor.parent_hash.unwrap()];
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Authority set in force after `node` along with the number of changes
+    /// that led to it.
+    fn authorities(&self, node: &ChainNode) -> (u64, &[AuthorityKey]) {
+        match (&node.authority_change, &self.proof_of_authority) {
+            (Some(hash), _) => {
+                let (sequence, authorities) = &self.authority_sets[hash];
+                (*sequence, authorities)
+            }
+            (None, Some(poa)) => (0, &poa.authorities),
+            (None, None) => (0, &[]),
+        }
+    }
+
+    /// Checks that the proposer of a block is one of the authorities, and
+    /// the one in turn with round robin. A change of the authority set
+    /// carried by the block has to be signed by a majority of them.
+    pub fn check_authority(&mut self, block: &Block) -> Result<(), Error> {
+        let parent = self.node(&block.header.parent_hash)?;
+
+        let round_robin = match &self.proof_of_authority {
+            Some(poa) => poa.round_robin,
+            None if block.header.authority_change.is_some() => {
+                return Err(Error::InvalidAuthorityChange(
+                    "chain has no proof of authority".into(),
+                ))
+            }
+            None => return Ok(()),
+        };
+
+        let (sequence, authorities) = self.authorities(&parent);
+        let proposer = AuthorityKey(block.header.proposer);
+
+        if !authorities.contains(&proposer) {
+            return Err(Error::InvalidProposer(format!(
+                "{} is not an authority",
+                proposer
+            )));
+        }
+
+        let in_turn = authorities[(block.header.height % authorities.len() as u64) as usize];
+        if round_robin && proposer != in_turn {
+            return Err(Error::InvalidProposer(format!(
+                "{} proposed out of turn, {} is in turn at height {}",
+                proposer, in_turn, block.header.height
+            )));
+        }
+
+        match &block.header.authority_change {
+            Some(change) => change.verify(authorities, sequence + 1),
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that a block is timestamped after its parent and after the
+    /// median time of the blocks before it.
+    pub fn check_timestamp(&mut self, block: &Block) -> Result<(), Error> {
+        let parent = self.node(&block.header.parent_hash)?;
+
+        if parent
+            .timestamp
+            .is_some_and(|timestamp| block.header.timestamp <= timestamp)
+        {
+            return Err(Error::TimestampBeforeParent);
+        }
+
+        if self
+            .median_time_past(parent)
+            .is_some_and(|timestamp| block.header.timestamp <= timestamp)
+        {
+            return Err(Error::TimestampBeforeMedian);
+        }
+
+        Ok(())
+    }
+
+    /// Median timestamp of the last blocks up to and including `node`, not
+    /// counting the genesis block.
+    fn median_time_past(&self, node: ChainNode) -> Option<DateTime<Utc>> {
+        let mut timestamps = Vec::with_capacity(MEDIAN_TIME_BLOCKS);
+        let mut cursor = Some(node);
+        while let Some(node) = cursor {
+            match node.timestamp {
+                Some(timestamp) if timestamps.len() < MEDIAN_TIME_BLOCKS => {
+                    timestamps.push(timestamp)
+                }
+                _ => break,
+            }
+
+            cursor = node.parent_hash.map(|hash| self.nodes[&hash]);
+        }
+
+        timestamps.sort();
+        timestamps.get(timestamps.len() / 2).copied()
+    }
+
+    /// Difficulty for the children of a new block, adjusted every retarget
+    /// interval by how long the blocks of the interval took compared to the
+    /// configured block time.
+    fn retarget(&self, parent: &ChainNode, timestamp: DateTime<Utc>) -> u32 {
+        let pow = match &self.proof_of_work {
+            Some(pow) => pow,
+            None => return 0,
+        };
+
+        let height = parent.height + 1;
+        if !height.is_multiple_of(pow.retarget_interval) {
+            return parent.difficulty;
+        }
+
+        // First block of the interval ending with the new block
+        let mut first = *parent;
+        for _ in 2..pow.retarget_interval {
+            first = self.nodes[&first.parent_hash.unwrap()];
+        }
+
+        let elapsed = match first.timestamp {
+            Some(first_timestamp) => (timestamp - first_timestamp).num_seconds(),
+            None => return parent.difficulty,
+        };
+        let expected = (pow.block_time * (pow.retarget_interval - 1)) as i64;
+
+        if elapsed < expected / 2 {
+            (parent.difficulty + 1).min(MAX_DIFFICULTY)
+        } else if elapsed > expected * 2 {
+            parent.difficulty.saturating_sub(1)
+        } else {
+            parent.difficulty
+        }
+    }
+
+    /// Checks that a block builds on the finalized block.
+    pub fn check_finality(&mut self, block: &Block) -> Result<(), Error> {
+        self.node(&block.header.parent_hash)?;
+
+        match self.descends_from(block.header.parent_hash, self.finalized) {
+            true => Ok(()),
+            false => Err(Error::FinalityConflict),
+        }
+    }
+
+    /// Whether `ancestor` is `hash` or one of its ancestors.
+    fn descends_from(&self, hash: BlockHash, ancestor: BlockHash) -> bool {
+        let ancestor_height = self.nodes[&ancestor].height;
+
+        let mut cursor = hash;
+        let mut node = self.nodes[&cursor];
+        while node.height > ancestor_height {
+            cursor = node.parent_hash.unwrap();
+            node = self.nodes[&cursor];
+        }
+
+        cursor == ancestor
+    }
+
+    /// Counts a validator's vote on a known checkpoint block, finalizing
+    /// the block once two thirds of the validators voted for it. The chain
+    /// reorganizes onto the finalized block if it is not canonical.
+    pub fn add_vote(&mut self, vote: &CheckpointVote) -> Result<Vec<ChainEvent>, Error> {
+        self.finality
+            .as_ref()
+            .ok_or_else(|| Error::InvalidVote("the chain has no validators".into()))?
+            .check_vote(vote)?;
+
+        let node = self.node(&vote.hash)?;
+        if node.height != vote.height {
+            return Err(Error::InvalidVote(format!(
+                "block at height {} voted for at height {}",
+                node.height, vote.height
+            )));
+        }
+
+        if node.height <= self.finalized_height() {
+            return Ok(Vec::new());
+        }
+
+        let finality = self.finality.as_mut().unwrap();
+        let finalized = finality.add_vote(vote)?;
+        self.store
+            .put(VOTES_KEY, &serialize(&finality.all_votes())?)?;
+        if !finalized {
+            return Ok(Vec::new());
+        }
+
+        // Only possible if a third of the validators voted for both sides
+        if !self.descends_from(vote.hash, self.finalized) {
+            error!(
+                "Validators finalized {:?} off the finalized chain",
+                vote.hash
+            );
+
+            return Err(Error::FinalityConflict);
+        }
+
+        // The heaviest chain is looked for among the descendants of the
+        // finalized block only
+        let mut events = Vec::new();
+        if !self.descends_from(self.head, vote.hash) {
+            let new_head = self.heaviest_descendant(vote.hash)?;
+
+            let old_head = self.head;
+            let (common_ancestor, retracted, enacted) = self.tree_route(old_head, new_head);
+
+            self.set_head(new_head, &retracted, &enacted)?;
+
+            events.push(ChainEvent::Reorg {
+                old_head,
+                new_head,
+                common_ancestor,
+                depth: retracted.len(),
+                retracted,
+                enacted,
+            });
+        }
+
+        let finality = self.finality.as_mut().unwrap();
+        let proof = finality.votes(&vote.hash);
+        finality.prune(node.height);
+
+        self.store.put(FINALITY_PROOF_KEY, &serialize(&proof)?)?;
+        self.store
+            .put(VOTES_KEY, &serialize(&finality.all_votes())?)?;
+        self.store.put(FINALIZED_KEY, &serialize(&vote.hash)?)?;
+        self.finalized = vote.hash;
+
+        events.push(ChainEvent::Finalized {
+            hash: vote.hash,
+            height: node.height,
+        });
+
+        Ok(events)
+    }
+
+    /// Returns the votes that finalized the finalized block, none if it is
+    /// the genesis block.
+    pub fn finality_proof(&self) -> Result<Vec<CheckpointVote>, Error> {
+        match self.store.get(FINALITY_PROOF_KEY) {
+            Some(bytes) => Ok(deserialize(&bytes)?),
+            None => Ok(Vec::new()),
+        }
+    }
+
+    fn children(&self, hash: &BlockHash) -> Vec<(BlockHash, u64)> {
+        self.store
+            .get(&children_key(hash))
+            .and_then(|bytes| deserialize(&bytes).ok())
+            .unwrap_or_default()
+    }
+
+    /// Records an inserted block as a child of its parent, numbered by
+    /// arrival.
+    fn index_child(&mut self, block: &Block) -> Result<(), Error> {
+        self.arrivals += 1;
+
+        let parent_hash = block.header.parent_hash;
+        let mut children = self.children(&parent_hash);
+        children.push((block.hash, self.arrivals));
+        self.store
+            .put(&children_key(&parent_hash), &serialize(&children)?)?;
+
+        self.store.put(ARRIVALS_KEY, &serialize(&self.arrivals)?)
+    }
+
+    /// Finds the heaviest block descending from `root`, including those
+    /// imported in an earlier run. Ties go to the block that arrived first,
+    /// as they do in `insert`.
+    fn heaviest_descendant(&mut self, root: BlockHash) -> Result<BlockHash, Error> {
+        let mut heaviest = (root, self.node(&root)?.weight, 0);
+
+        let mut pending = vec![root];
+        while let Some(hash) = pending.pop() {
+            for (child, arrival) in self.children(&hash) {
+                let (_, weight, first_arrival) = heaviest;
+                let child_weight = self.node(&child)?.weight;
+
+                if child_weight > weight || (child_weight == weight && arrival < first_arrival) {
+                    heaviest = (child, child_weight, arrival);
+                }
+
+                pending.push(child);
+            }
+        }
+
+        Ok(heaviest.0)
+    }
+
+    /// Adds a block to the tree, and its authority set if it changes it.
+    fn add_node(&mut self, parent: &ChainNode, block: &Block) -> ChainNode {
+        let authority_change = match &block.header.authority_change {
+            Some(change) => {
+                self.authority_sets
+                    .insert(block.hash, (change.sequence, change.authorities.clone()));
+
+                Some(block.hash)
+            }
+            None => parent.authority_change,
+        };
+
+        let node = ChainNode {
+            parent_hash: Some(block.header.parent_hash),
+            height: parent.height + 1,
+            weight: parent.weight + block.score(),
+            difficulty: self.retarget(parent, block.header.timestamp),
+            timestamp: Some(block.header.timestamp),
+            proposer: Some(block.header.proposer),
+            authority_change: authority_change,
+        };
+        self.nodes.insert(block.hash, node);
+
+        node
+    }
+
+    /// Checks a block against the chain, stores it and inserts it into the
+    /// block tree, then applies the heaviest-chain rule. Nothing is stored
+    /// for a block that fails the checks. Returns an event if the canonical
+    /// head changed.
+    pub fn insert(&mut self, signed_block: &SignedBlock) -> Result<Option<ChainEvent>, Error> {
+        let block = &signed_block.message;
+        if self.store.get(block.hash.as_bytes()).is_some() {
+            return Err(Error::DuplicateBlock);
+        }
+
+        let parent = self.node(&block.header.parent_hash)?;
+        if block.header.height != parent.height + 1 {
+            return Err(Error::InvalidHeight);
+        }
+
+        self.check_finality(block)?;
+        self.check_words(block)?;
+        self.check_work(block)?;
+        self.check_proposer(block)?;
+        self.check_timestamp(block)?;
+        self.check_authority(block)?;
+
+        self.store
+            .put(block.hash.as_bytes(), &serialize(signed_block)?)?;
+
+        let node = self.add_node(&parent, block);
+        self.index_child(block)?;
+
+        // Ties are resolved in favour of the block we have seen first
+        if node.weight <= self.head_weight() {
+            return Ok(None);
+        }
+
+        let old_head = self.head;
+        let (common_ancestor, retracted, enacted) = self.tree_route(old_head, block.hash);
+
+        self.set_head(block.hash, &retracted, &enacted)?;
+
+        if retracted.is_empty() {
+            return Ok(Some(ChainEvent::Extended { head: block.hash }));
+        }
+
+        Ok(Some(ChainEvent::Reorg {
+            old_head,
+            new_head: block.hash,
+            common_ancestor,
+            depth: retracted.len(),
+            retracted,
+            enacted,
+        }))
+    }
+
+    /// Finds the common ancestor of two blocks in the tree along with the
+    /// blocks leading from it to either of them.
+    fn tree_route(
+        &self,
+        from: BlockHash,
+        to: BlockHash,
+    ) -> (BlockHash, Vec<BlockHash>, Vec<BlockHash>) {
+        let mut retracted = Vec::new();
+        let mut enacted = Vec::new();
+
+        let (mut from, mut to) = (from, to);
+        let (mut from_node, mut to_node) = (self.nodes[&from], self.nodes[&to]);
+
+        while from_node.height > to_node.height {
+            retracted.push(from);
+            from = from_node.parent_hash.unwrap();
+            from_node = self.nodes[&from];
+        }
+
+        while to_node.height > from_node.height {
+            enacted.push(to);
+            to = to_node.parent_hash.unwrap();
+            to_node = self.nodes[&to];
+        }
+
+        while from != to {
+            retracted.push(from);
+            enacted.push(to);
+            from = from_node.parent_hash.unwrap();
+            to = to_node.parent_hash.unwrap();
+            from_node = self.nodes[&from];
+            to_node = self.nodes[&to];
+        }
+
+        enacted.reverse();
+
+        (from, retracted, enacted)
+    }
+
+    /// Moves the head and rewrites the canonical height and used word
+    /// indexes along the route from the old head.
+    fn set_head(
+        &mut self,
+        hash: BlockHash,
+        retracted: &[BlockHash],
+        enacted: &[BlockHash],
+    ) -> Result<(), Error> {
+        let height = self.nodes[&hash].height;
+
+        for retracted_hash in retracted {
+            let retracted_height = self.nodes[retracted_hash].height;
+            if retracted_height > height {
+                self.store.delete(&canonical_key(retracted_height))?;
+            }
+
+            for word in self.load(retracted_hash)?.message.words() {
+                let mut heights = self.word_heights(word);
+                heights.retain(|height| *height != retracted_height);
+
+                match heights.is_empty() {
+                    true => self.store.delete(&word_key(word))?,
+                    false => self.store.put(&word_key(word), &serialize(&heights)?)?,
+                }
+            }
+        }
+
+        for enacted_hash in enacted {
+            let enacted_height = self.nodes[enacted_hash].height;
+            self.store
+                .put(&canonical_key(enacted_height), &serialize(enacted_hash)?)?;
+
+            self.index_words(enacted_hash, enacted_height)?;
+        }
+
+        self.store.put(HEAD_KEY, &serialize(&hash)?)?;
+        self.head = hash;
+
+        Ok(())
+    }
+
+    fn index_words(&self, hash: &BlockHash, height: u64) -> Result<(), Error> {
+        for word in self.load(hash)?.message.words() {
+            let mut heights = self.word_heights(word);
+            if let Err(index) = heights.binary_search(&height) {
+                heights.insert(index, height);
+                self.store.put(&word_key(word), &serialize(&heights)?)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn rebuild_word_index(&mut self) -> Result<(), Error> {
+        info!("Indexing used words up to height {}", self.head_height());
+
+        for height in 1..=self.head_height() {
+            if let Some(hash) = self.canonical_hash(height) {
+                self.index_words(&hash, height)?;
+            }
+        }
+
+        self.store.put(WORD_INDEX_KEY, &[])
+    }
+
+    fn load(&self, hash: &BlockHash) -> Result<SignedBlock, Error> {
+        let bytes = self
+            .store
+            .get(hash.as_bytes())
+            .ok_or(Error::UnknownParentBlock)?;
+
+        Ok(deserialize(&bytes)?)
+    }
+
+    /// Looks up a block in the tree, loading it and any missing ancestors
+    /// from the store if they were imported in an earlier run.
+    fn node(&mut self, hash: &BlockHash) -> Result<ChainNode, Error> {
+        if let Some(node) = self.nodes.get(hash) {
+            return Ok(*node);
+        }
+
+        let mut missing = Vec::new();
+        let mut cursor = *hash;
+        let mut ancestor = loop {
+            if let Some(node) = self.nodes.get(&cursor) {
+                break *node;
+            }
+
+            let block = self.load(&cursor)?.message;
+
+            cursor = block.header.parent_hash;
+            missing.push(block);
+        };
+
+        while let Some(block) = missing.pop() {
+            ancestor = self.add_node(&ancestor, &block);
+        }
+
+        Ok(ancestor)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    use std::sync::atomic::AtomicBool;
+
+    use chrono::Duration;
+    use libp2p::identity::ed25519::Keypair;
+
+    use crate::authority::AuthorityChange;
+    use crate::spec::Finality;
+
+    fn open_store(name: &str) -> Arc<DiscStore> {
+        let path = Path::new(".data").join(".test").join(name);
+        let _ = std::fs::remove_dir_all(&path);
+
+        let store = DiscStore::open(&path).ok().unwrap();
+        let (_, genesis_hash, genesis_block) = Block::genesis_block();
+        store.put(&genesis_hash, &genesis_block).unwrap();
+
+        Arc::new(store)
+    }
+
+    fn import(chain: &mut ChainState, words: &[&str], parent: BlockHash) -> BlockHash {
+        let proposer = Keypair::generate();
+        let spec = ChainSpec::default();
+        let wordlist = words.iter().map(|w| w.to_string()).collect();
+        let height = chain.height(&parent).unwrap() + 1;
+        let block = Block::new(wordlist, proposer.public(), parent, height, &spec).unwrap();
+        chain.insert(&block.clone().sign(&proposer)).unwrap();
+
+        block.hash
+    }
+
+    #[test]
+    fn test_heaviest_chain() {
+        let store = open_store("chain_heaviest");
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["with", "from", "have", "they"], genesis);
+        assert_eq!(chain.head(), a1);
+        assert_eq!(chain.head_weight(), 39);
+
+        // Lighter than the head, the longer branch only wins once it weighs
+        // more in total
+        let b1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        assert_eq!(chain.head(), a1);
+
+        let b2 = import(&mut chain, &["word", "will", "more", "what"], b1);
+        assert_eq!(chain.head(), b2);
+        assert_eq!(chain.head_weight(), 55);
+
+        // A single block of rare letters outweighs both
+        let c1 = import(&mut chain, &["jazz", "quiz", "jump", "joke"], genesis);
+        assert_eq!(chain.head(), c1);
+        assert_eq!(chain.head_height(), 1);
+        assert_eq!(chain.canonical_hash(2), None);
+
+        // Weights are restored along with the block tree
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        assert_eq!(chain.head_weight(), 81);
+        import(&mut chain, &["ten", "one", "its", "not"], b2);
+        assert_eq!(chain.head(), c1);
+    }
+
+    #[test]
+    fn test_reorg() {
+        let store = open_store("chain_reorg");
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        let a2 = import(&mut chain, &["with", "from", "have", "they"], a1);
+        let b2 = import(&mut chain, &["word", "will", "more", "what"], a1);
+        assert_eq!(chain.head(), a2);
+
+        let proposer = Keypair::generate();
+        let spec = ChainSpec::default();
+        let wordlist = ["about", "been", "into", "some"];
+        let wordlist = wordlist.iter().map(|w| w.to_string()).collect();
+        let b3 = Block::new(wordlist, proposer.public(), b2, 3, &spec).unwrap();
+
+        assert_eq!(
+            chain.insert(&b3.clone().sign(&proposer)).unwrap(),
+            Some(ChainEvent::Reorg {
+                old_head: a2,
+                new_head: b3.hash,
+                common_ancestor: a1,
+                depth: 1,
+                retracted: vec![a2],
+                enacted: vec![b2, b3.hash],
+            })
+        );
+        assert_eq!(chain.recent(5), vec![a1, b2, b3.hash]);
+        assert_eq!(chain.canonical_hash(1), Some(a1));
+        assert_eq!(chain.canonical_hash(2), Some(b2));
+        assert_eq!(chain.canonical_hash(3), Some(b3.hash));
+        assert_eq!(chain.canonical_hash(4), None);
+    }
+
+    #[test]
+    fn test_reused_words() {
+        let store = open_store("chain_reused_words");
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        let a2 = import(&mut chain, &["with", "from", "have", "they"], a1);
+
+        let proposer = Keypair::generate();
+        let wordlist = ["word", "will", "more", "this"];
+        let wordlist = wordlist.iter().map(|w| w.to_string()).collect();
+        let a3 = Block::new(wordlist, proposer.public(), a2, 3, &ChainSpec::default()).unwrap();
+        assert_eq!(
+            chain.check_words(&a3),
+            Err(Error::ReusedWord("this".to_string()))
+        );
+
+        // Words of a2 are free to use on a competing branch, and again on
+        // the original one once the branch took over
+        let b2 = import(&mut chain, &["with", "from", "have", "what"], a1);
+        let b3 = import(&mut chain, &["word", "will", "more", "they"], b2);
+        assert_eq!(chain.head(), b3);
+        assert_eq!(chain.word_heights("what"), vec![2]);
+        assert_eq!(chain.word_heights("they"), vec![3]);
+
+        let a3 = import(&mut chain, &["about", "been", "into", "what"], a2);
+        let a4 = import(&mut chain, &["some", "when", "your", "like"], a3);
+        assert_eq!(chain.head(), a4);
+        assert_eq!(chain.word_heights("what"), vec![3]);
+        assert_eq!(chain.word_heights("they"), vec![2]);
+        assert_eq!(chain.word_heights("will"), Vec::<u64>::new());
+
+        // The index is rebuilt for databases predating it
+        store.delete(WORD_INDEX_KEY).unwrap();
+        store.delete(&word_key("when")).unwrap();
+        let chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        assert_eq!(chain.word_heights("when"), vec![4]);
+    }
+
+    #[test]
+    fn test_word_cooldown() {
+        let store = open_store("chain_word_cooldown");
+        let spec = ChainSpec {
+            word_cooldown: Some(2),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        let a2 = import(&mut chain, &["with", "from", "have", "they"], a1);
+
+        let proposer = Keypair::generate();
+        let wordlist = ["word", "will", "more", "this"];
+        let wordlist = wordlist.iter().map(|w| w.to_string()).collect();
+        let a3 = Block::new(wordlist, proposer.public(), a2, 3, &ChainSpec::default()).unwrap();
+        assert!(chain.check_words(&a3).is_ok());
+
+        let wordlist = ["word", "will", "more", "they"];
+        let wordlist = wordlist.iter().map(|w| w.to_string()).collect();
+        let a3 = Block::new(wordlist, proposer.public(), a2, 3, &ChainSpec::default()).unwrap();
+        assert_eq!(
+            chain.check_words(&a3),
+            Err(Error::WordCooldown("they".to_string(), 4))
+        );
+
+        // A word used twice keeps its earlier use once the later is retracted
+        let a3 = import(&mut chain, &["word", "will", "more", "this"], a2);
+        let b3 = import(&mut chain, &["about", "been", "into", "some"], a2);
+        let b4 = import(&mut chain, &["when", "your", "like", "what"], b3);
+        assert_eq!(chain.head(), b4);
+        assert_eq!(chain.word_heights("this"), vec![1]);
+
+        let a4 = import(&mut chain, &["over", "only", "just", "from"], a3);
+        import(&mut chain, &["also", "back", "each", "into"], a4);
+        assert_eq!(chain.word_heights("this"), vec![1, 3]);
+        assert_eq!(chain.word_heights("from"), vec![2, 4]);
+    }
+
+    fn mine(
+        chain: &mut ChainState,
+        words: &[&str],
+        parent: BlockHash,
+        proposer: &Keypair,
+        timestamp: DateTime<Utc>,
+    ) -> Block {
+        let wordlist = words.iter().map(|w| w.to_string()).collect();
+        let height = chain.height(&parent).unwrap() + 1;
+        let mut block = Block::new(
+            wordlist,
+            proposer.public(),
+            parent,
+            height,
+            &ChainSpec::default(),
+        )
+        .unwrap();
+        block.header.timestamp = timestamp;
+
+        let difficulty = chain.next_difficulty(&parent).unwrap();
+        assert!(block.mine(difficulty, &AtomicBool::new(false)));
+
+        block
+    }
+
+    #[test]
+    fn test_proof_of_work() {
+        let store = open_store("chain_proof_of_work");
+        let spec = ChainSpec {
+            proof_of_work: Some(ProofOfWork {
+                difficulty: 2,
+                retarget_interval: 2,
+                block_time: 60,
+            }),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+        let proposer = Keypair::generate();
+        let start = Utc::now();
+
+        let mut b1 = mine(
+            &mut chain,
+            &["and", "for", "that", "this"],
+            genesis,
+            &proposer,
+            start,
+        );
+        while b1.hash.leading_zeros() >= 2 {
+            b1.header.nonce += 1;
+            b1.hash = b1.header.hash();
+        }
+        assert_eq!(chain.check_work(&b1), Err(Error::InsufficientWork(2)));
+
+        let b1 = mine(
+            &mut chain,
+            &["and", "for", "that", "this"],
+            genesis,
+            &proposer,
+            start,
+        );
+        chain.insert(&b1.clone().sign(&proposer)).unwrap();
+        assert_eq!(chain.next_difficulty(&b1.hash), Ok(2));
+
+        // Blocks faster than the block time raise the difficulty, slower
+        // ones lower it
+        let fast = start + Duration::seconds(10);
+        let b2 = mine(
+            &mut chain,
+            &["with", "from", "have", "they"],
+            b1.hash,
+            &proposer,
+            fast,
+        );
+        chain.insert(&b2.clone().sign(&proposer)).unwrap();
+        assert_eq!(chain.next_difficulty(&b2.hash), Ok(3));
+
+        let slow = start + Duration::seconds(200);
+        let c2 = mine(
+            &mut chain,
+            &["word", "will", "more", "what"],
+            b1.hash,
+            &proposer,
+            slow,
+        );
+        chain.insert(&c2.clone().sign(&proposer)).unwrap();
+        assert_eq!(chain.next_difficulty(&c2.hash), Ok(1));
+
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        assert_eq!(chain.next_difficulty(&b2.hash), Ok(3));
+    }
+
+    #[test]
+    fn test_proposer_limits() {
+        let store = open_store("chain_proposer_limits");
+        let spec = ChainSpec {
+            min_block_interval: Some(10),
+            max_consecutive_blocks: Some(2),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+        let (alice, bob) = (Keypair::generate(), Keypair::generate());
+        let start = Utc::now();
+
+        let b1 = mine(
+            &mut chain,
+            &["and", "for", "that", "this"],
+            genesis,
+            &alice,
+            start,
+        );
+        chain.insert(&b1.clone().sign(&alice)).unwrap();
+
+        let words = ["with", "from", "have", "they"];
+        let early = start + Duration::seconds(5);
+        let b2 = mine(&mut chain, &words, b1.hash, &alice, early);
+        assert_eq!(
+            chain.check_proposer(&b2),
+            Err(Error::BlockIntervalTooShort(10))
+        );
+
+        let b2 = mine(
+            &mut chain,
+            &words,
+            b1.hash,
+            &alice,
+            start + Duration::seconds(10),
+        );
+        chain.insert(&b2.clone().sign(&alice)).unwrap();
+
+        let words = ["word", "will", "more", "what"];
+        let later = start + Duration::seconds(20);
+        let b3 = mine(&mut chain, &words, b2.hash, &alice, later);
+        assert_eq!(
+            chain.check_proposer(&b3),
+            Err(Error::TooManyConsecutiveBlocks(2))
+        );
+
+        let b3 = mine(&mut chain, &words, b2.hash, &bob, later);
+        chain.insert(&b3.clone().sign(&bob)).unwrap();
+        assert_eq!(chain.head(), b3.hash);
+    }
+
+    #[test]
+    fn test_timestamps() {
+        let store = open_store("chain_timestamps");
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+        let proposer = Keypair::generate();
+        let start = Utc::now();
+
+        let words = ["and", "for", "that", "this"];
+        let b1 = mine(&mut chain, &words, genesis, &proposer, start);
+        chain.insert(&b1.clone().sign(&proposer)).unwrap();
+
+        let words = ["with", "from", "have", "they"];
+        let b2 = mine(&mut chain, &words, b1.hash, &proposer, start);
+        assert_eq!(
+            chain.check_timestamp(&b2),
+            Err(Error::TimestampBeforeParent)
+        );
+
+        let second = start + Duration::seconds(10);
+        let b2 = mine(&mut chain, &words, b1.hash, &proposer, second);
+        chain.insert(&b2.clone().sign(&proposer)).unwrap();
+
+        let words = ["word", "will", "more", "what"];
+        let third = start + Duration::seconds(30);
+        let b3 = mine(&mut chain, &words, b2.hash, &proposer, third);
+        chain.insert(&b3.clone().sign(&proposer)).unwrap();
+
+        assert_eq!(chain.median_time_past(chain.nodes[&b1.hash]), Some(start));
+        assert_eq!(chain.median_time_past(chain.nodes[&b3.hash]), Some(second));
+        assert_eq!(chain.median_time_past(chain.nodes[&genesis]), None);
+    }
+
+    #[test]
+    fn test_proof_of_authority() {
+        let store = open_store("chain_proof_of_authority");
+        let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::generate()).collect();
+        let keys: Vec<AuthorityKey> = keypairs
+            .iter()
+            .map(|keypair| AuthorityKey::from(&keypair.public()))
+            .collect();
+        let (alice, bob, carol, dave) = (&keypairs[0], &keypairs[1], &keypairs[2], &keypairs[3]);
+
+        let spec = ChainSpec {
+            proof_of_authority: Some(ProofOfAuthority {
+                authorities: keys[..3].to_vec(),
+                round_robin: true,
+            }),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+        let start = Utc::now();
+
+        let words = ["and", "for", "that", "this"];
+        let b1 = mine(&mut chain, &words, genesis, dave, start);
+        assert!(matches!(
+            chain.check_authority(&b1),
+            Err(Error::InvalidProposer(_))
+        ));
+        let b1 = mine(&mut chain, &words, genesis, alice, start);
+        assert!(matches!(
+            chain.check_authority(&b1),
+            Err(Error::InvalidProposer(_))
+        ));
+        let b1 = mine(&mut chain, &words, genesis, bob, start);
+        chain.insert(&b1.clone().sign(bob)).unwrap();
+
+        // Carol hands over to Alice and Dave, which takes two signatures
+        let mut change = AuthorityChange {
+            sequence: 1,
+            authorities: vec![keys[0], keys[3]],
+            signatures: Vec::new(),
+        };
+        change.sign(alice);
+
+        let words = ["with", "from", "have", "they"];
+        let second = start + Duration::seconds(10);
+        let b2 = mine(&mut chain, &words, b1.hash, carol, second);
+        let b2 = b2.with_authority_change(change.clone());
+        assert!(matches!(
+            chain.check_authority(&b2),
+            Err(Error::InvalidAuthorityChange(_))
+        ));
+
+        change.sign(bob);
+        let b2 = b2.with_authority_change(change);
+        chain.insert(&b2.clone().sign(carol)).unwrap();
+
+        let words = ["word", "will", "more", "what"];
+        let third = start + Duration::seconds(20);
+        let b3 = mine(&mut chain, &words, b2.hash, dave, third);
+        chain.insert(&b3.clone().sign(dave)).unwrap();
+
+        // The changed set is restored along with the block tree
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let words = ["about", "been", "into", "some"];
+        let fourth = start + Duration::seconds(30);
+        let b4 = mine(&mut chain, &words, b3.hash, carol, fourth);
+        assert!(matches!(
+            chain.check_authority(&b4),
+            Err(Error::InvalidProposer(_))
+        ));
+        let b4 = mine(&mut chain, &words, b3.hash, alice, fourth);
+        assert_eq!(chain.check_authority(&b4), Ok(()));
+    }
+
+    #[test]
+    fn test_finality() {
+        let store = open_store("chain_finality");
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate()).collect();
+        let spec = ChainSpec {
+            finality: Some(Finality {
+                validators: keypairs
+                    .iter()
+                    .map(|keypair| AuthorityKey::from(&keypair.public()))
+                    .collect(),
+                checkpoint_interval: 2,
+            }),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["jazz", "quiz", "jump", "joke"], genesis);
+        let a2 = import(&mut chain, &["ten", "one", "its", "not"], a1);
+        let b1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        let b2 = import(&mut chain, &["with", "from", "have", "they"], b1);
+        assert_eq!(chain.head(), a2);
+        assert_eq!(chain.finalized_height(), 0);
+
+        assert!(chain.is_checkpoint(2));
+        assert!(!chain.is_checkpoint(3));
+
+        let vote = CheckpointVote::new(a1, 2, &keypairs[0]);
+        assert!(matches!(chain.add_vote(&vote), Err(Error::InvalidVote(_))));
+
+        // Two of three validators finalize the lighter branch
+        let vote = CheckpointVote::new(b2, 2, &keypairs[0]);
+        assert_eq!(chain.add_vote(&vote), Ok(Vec::new()));
+        let vote = CheckpointVote::new(b2, 2, &keypairs[1]);
+        let events = chain.add_vote(&vote).unwrap();
+        assert!(matches!(
+            events[0],
+            ChainEvent::Reorg { new_head, depth: 2, .. } if new_head == b2
+        ));
+        assert_eq!(
+            events[1],
+            ChainEvent::Finalized {
+                hash: b2,
+                height: 2
+            }
+        );
+        assert_eq!(chain.head(), b2);
+
+        // The other branch can no longer be built on, however heavy
+        let wordlist = ["word", "will", "more", "what"]
+            .iter()
+            .map(|w| w.to_string())
+            .collect();
+        let a3 = Block::new(wordlist, keypairs[0].public(), a2, 3, &spec).unwrap();
+        assert_eq!(
+            chain.insert(&a3.clone().sign(&keypairs[0])),
+            Err(Error::FinalityConflict)
+        );
+        assert!(store.get(a3.hash.as_bytes()).is_none());
+
+        import(&mut chain, &["word", "will", "more", "what"], b2);
+        assert_eq!(chain.head_height(), 3);
+
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        assert_eq!(chain.finalized_height(), 2);
+        assert_eq!(
+            chain.insert(&a3.clone().sign(&keypairs[0])),
+            Err(Error::FinalityConflict)
+        );
+    }
+
+    #[test]
+    fn test_finality_restart() {
+        let store = open_store("chain_finality_restart");
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate()).collect();
+        let spec = ChainSpec {
+            finality: Some(Finality {
+                validators: keypairs
+                    .iter()
+                    .map(|keypair| AuthorityKey::from(&keypair.public()))
+                    .collect(),
+                checkpoint_interval: 2,
+            }),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["jazz", "quiz", "jump", "joke"], genesis);
+        let a2 = import(&mut chain, &["ten", "one", "its", "not"], a1);
+        let vote = CheckpointVote::new(a2, 2, &keypairs[0]);
+        assert_eq!(chain.add_vote(&vote), Ok(Vec::new()));
+        assert_eq!(chain.finality_proof(), Ok(Vec::new()));
+
+        // Votes counted before a restart still count
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        assert_eq!(chain.add_vote(&vote), Err(Error::DuplicateVote));
+        let events = chain
+            .add_vote(&CheckpointVote::new(a2, 2, &keypairs[1]))
+            .unwrap();
+        assert!(matches!(events[0], ChainEvent::Finalized { hash, .. } if hash == a2));
+
+        let proof = chain.finality_proof().unwrap();
+        assert_eq!(proof.len(), 2);
+        assert!(proof.contains(&vote));
+
+        // Late votes are not counted, nor kept
+        let vote = CheckpointVote::new(a2, 2, &keypairs[2]);
+        assert_eq!(chain.add_vote(&vote), Ok(Vec::new()));
+        assert!(chain.finality.as_ref().unwrap().all_votes().is_empty());
+
+        let chain = ChainState::new(&store, &spec).unwrap();
+        assert_eq!(chain.finalized_height(), 2);
+        assert_eq!(chain.finality_proof().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_finalize_stored_branch() {
+        let store = open_store("chain_finalize_stored");
+        let keypairs: Vec<Keypair> = (0..3).map(|_| Keypair::generate()).collect();
+        let spec = ChainSpec {
+            finality: Some(Finality {
+                validators: keypairs
+                    .iter()
+                    .map(|keypair| AuthorityKey::from(&keypair.public()))
+                    .collect(),
+                checkpoint_interval: 2,
+            }),
+            ..ChainSpec::default()
+        };
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["jazz", "quiz", "jump", "joke"], genesis);
+        let a2 = import(&mut chain, &["ten", "one", "its", "not"], a1);
+        let b1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        let b2 = import(&mut chain, &["with", "from", "have", "they"], b1);
+        let b3 = import(&mut chain, &["word", "will", "more", "what"], b2);
+        import(&mut chain, &["some", "than", "them", "each"], b2);
+        let a3 = import(&mut chain, &["said", "many", "like", "long"], a2);
+        assert_eq!(chain.head(), a3);
+
+        // Only the canonical chain is loaded after a restart, the heaviest
+        // of the equally heavy descendants of the finalized block is the
+        // one that arrived first
+        let mut chain = ChainState::new(&store, &spec).unwrap();
+        chain
+            .add_vote(&CheckpointVote::new(b2, 2, &keypairs[0]))
+            .unwrap();
+        chain
+            .add_vote(&CheckpointVote::new(b2, 2, &keypairs[1]))
+            .unwrap();
+        assert_eq!(chain.head(), b3);
+    }
+
+    #[test]
+    fn test_reload_head() {
+        let store = open_store("chain_reload");
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        let (genesis, _, _) = Block::genesis_block();
+
+        let a1 = import(&mut chain, &["and", "for", "that", "this"], genesis);
+        let a2 = import(&mut chain, &["with", "from", "have", "they"], a1);
+
+        let mut chain = ChainState::new(&store, &ChainSpec::default()).unwrap();
+        assert_eq!(chain.head(), a2);
+        assert_eq!(chain.head_height(), 2);
+
+        import(&mut chain, &["word", "will", "more", "what"], a1);
+        assert_eq!(chain.head(), a2);
+    }
+}
@@ -4,20 +4,35 @@ use std::io::BufRead;
 use std::io::BufReader;
 use std::path::Path;
 
+use crate::block::BlockHash;
+use crate::errors::Error;
+
 lazy_static! {
-    pub static ref DICTIONARY: BTreeSet<String> = {
-        let mut d = BTreeSet::<String>::new();
-
-        let p = Path::new(".data").join("dictionary.txt");
-        let f = File::open(&p).unwrap();
-        let wordlist = BufReader::new(&f);
-        for word in wordlist.lines() {
-            let w = word.unwrap();
-            d.insert(w);
-        }
-
-        d
-    };
+    pub static ref DICTIONARY: BTreeSet<String> =
+        load(&Path::new(".data").join("dictionary.txt")).unwrap();
+
+    /// Digest over the sorted dictionary, used to tell whether two peers
+    /// validate blocks against the same words.
+    pub static ref DICTIONARY_DIGEST: BlockHash = digest(&DICTIONARY);
+}
+
+/// Reads a dictionary file with one word per line.
+pub fn load(path: &Path) -> Result<BTreeSet<String>, Error> {
+    let f = File::open(path)?;
+    let wordlist = BufReader::new(&f);
+
+    let mut d = BTreeSet::<String>::new();
+    for word in wordlist.lines() {
+        d.insert(word?);
+    }
+
+    Ok(d)
+}
+
+pub fn digest(dictionary: &BTreeSet<String>) -> BlockHash {
+    let words: Vec<&str> = dictionary.iter().map(String::as_str).collect();
+
+    BlockHash::digest(words.join("\n").as_bytes())
 }
 
 #[test]
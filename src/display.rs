@@ -8,8 +8,12 @@ use crossterm::{
     QueueableCommand, Result,
 };
 
-const RECENT_BLOCKS_TO_DISPLAY: u16 = 5;
+use crate::block::WordRule;
+
+pub const RECENT_BLOCKS_TO_DISPLAY: usize = 5;
 const BLOCKCHAIN_DISPLAY_ROW: u16 = 30;
+const PEERS_DISPLAY_ROW: u16 = BLOCKCHAIN_DISPLAY_ROW + 11;
+const PEERS_TO_DISPLAY: usize = 10;
 
 lazy_static! {
     static ref NOTICE_VALID_BLOCK: StyledContent<String> = {
@@ -22,6 +26,21 @@ lazy_static! {
             .with(Color::Red)
             .attribute(Attribute::Bold)
     };
+    static ref NOTICE_REORG: StyledContent<String> = {
+        style("Chain Reorganized".to_string())
+            .with(Color::Yellow)
+            .attribute(Attribute::Bold)
+    };
+    static ref NOTICE_WORD_PROOF: StyledContent<String> = {
+        style("Word Included".to_string())
+            .with(Color::Green)
+            .attribute(Attribute::Bold)
+    };
+    static ref FINALIZED_BLOCK_DISPLAY: StyledContent<String> = {
+        style("FINAL".to_string())
+            .with(Color::Green)
+            .attribute(Attribute::Bold)
+    };
     static ref GENESIS_BLOCK_DISPLAY: StyledContent<String> = {
         style("GENESIS".to_string())
             .with(Color::DarkYellow)
@@ -29,36 +48,27 @@ lazy_static! {
     };
 }
 
-pub struct Display {
-    blockchain_display_column: u16,
-    block_count: u16,
-}
+pub struct Display;
 
 impl Display {
-    pub fn new() -> Self {
-        Display {
-            blockchain_display_column: 10,
-            block_count: 1,
-        }
-    }
-
-    pub fn init() -> Result<()> {
+    /// Draws the welcome text, describing the word rules blocks are
+    /// currently checked against.
+    pub fn init(rules: &[WordRule]) -> Result<()> {
         let mut stdout = stdout();
 
-        let example_words_style = style("this monkey is awesome").with(Color::DarkYellow);
+        let rules: Vec<String> = rules.iter().map(|rule| rule.to_string()).collect();
+        let rules_style = style(rules.join(", ")).with(Color::DarkYellow);
 
         stdout
             .queue(SetTitle("Monkey - The p2p toy blockchain"))?
             .queue(MoveToNextLine(3))?
             .queue(Print(
-                "Welcome to Monkey! In Monkey world, a block consists of a set of 4 English words.",
+                "Welcome to Monkey! In Monkey world, a block consists of a list of words.",
             ))?
             .queue(MoveToNextLine(2))?
-            .queue(Print(
-                "Please enter 4 English words separated by spaces. Example:",
-            ))?
+            .queue(Print("Please enter words separated by spaces, with:"))?
             .queue(MoveToNextLine(1))?
-            .queue(PrintStyledContent(example_words_style))?
+            .queue(PrintStyledContent(rules_style))?
             .queue(MoveToNextLine(2))?
             .queue(Print("Start creating monkey blocks!"))?
             .queue(MoveToNextLine(2))?
@@ -102,47 +112,130 @@ impl Display {
         Ok(())
     }
 
-    pub fn new_block(&mut self, lines: Vec<String>) -> Result<()> {
+    pub fn notice_reorg(depth: usize) -> Result<()> {
+        let mut stdout = stdout();
+
+        stdout
+            .queue(MoveTo(0, 2))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(PrintStyledContent(NOTICE_REORG.clone()))?
+            .queue(Print(format!(" ({} blocks rolled back)", depth)))?
+            .queue(RestorePosition)?;
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    pub fn notice_word_proof(word: &str, height: u64) -> Result<()> {
+        let mut stdout = stdout();
+
+        stdout
+            .queue(MoveTo(0, 2))?
+            .queue(Clear(ClearType::CurrentLine))?
+            .queue(PrintStyledContent(NOTICE_WORD_PROOF.clone()))?
+            .queue(Print(format!(" ({} in block {})", word, height)))?
+            .queue(RestorePosition)?;
+
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Lists peer scores below the chain, banned peers highlighted in red.
+    pub fn draw_peers(peers: Vec<(String, i32, bool)>) -> Result<()> {
         let mut stdout = stdout();
 
-        let styled_content = |line: String| {
-            style(line)
-                .with(Color::DarkYellow)
-                .attribute(Attribute::Bold)
+        for i in 0..=PEERS_TO_DISPLAY as u16 {
+            stdout
+                .queue(MoveTo(0, PEERS_DISPLAY_ROW + i))?
+                .queue(Clear(ClearType::CurrentLine))?;
+        }
+
+        stdout
+            .queue(MoveTo(2, PEERS_DISPLAY_ROW))?
+            .queue(Print(format!("Peer scores ({} penalized)", peers.len())))?;
+
+        for (index, (peer_id, score, banned)) in
+            peers.into_iter().take(PEERS_TO_DISPLAY).enumerate()
+        {
+            let row = PEERS_DISPLAY_ROW + 1 + u16::try_from(index).unwrap();
+            let line = format!("{:>6}  {}", score, peer_id);
+
+            stdout.queue(MoveTo(2, row))?;
+            match banned {
+                true => stdout.queue(PrintStyledContent(
+                    style(format!("{}  banned", line)).with(Color::Red),
+                ))?,
+                false => stdout.queue(Print(line))?,
+            };
+        }
+
+        stdout.queue(RestorePosition)?;
+        stdout.flush()?;
+
+        Ok(())
+    }
+
+    /// Redraws the most recent canonical blocks, oldest first, along with
+    /// whether they are finalized. The genesis marker is drawn in front of
+    /// them if `from_genesis` is set.
+    pub fn draw_chain(blocks: Vec<(Vec<String>, bool)>, from_genesis: bool) -> Result<()> {
+        let mut stdout = stdout();
+
+        let styled_content = |line: String, finalized: bool| {
+            let color = match finalized {
+                true => Color::Green,
+                false => Color::DarkYellow,
+            };
+
+            style(line).with(color).attribute(Attribute::Bold)
         };
 
-        if self.block_count > RECENT_BLOCKS_TO_DISPLAY {
-            for i in 0..9 {
+        for i in 0..9 {
+            stdout
+                .queue(MoveTo(0, BLOCKCHAIN_DISPLAY_ROW + i + 1))?
+                .queue(Clear(ClearType::CurrentLine))?;
+        }
+
+        let mut column = match from_genesis {
+            true => {
                 stdout
-                    .queue(MoveTo(
-                        self.blockchain_display_column,
-                        BLOCKCHAIN_DISPLAY_ROW + i + 1,
-                    ))?
-                    .queue(Clear(ClearType::CurrentLine))?;
+                    .queue(MoveTo(2, BLOCKCHAIN_DISPLAY_ROW + 5))?
+                    .queue(PrintStyledContent(GENESIS_BLOCK_DISPLAY.clone()))?;
+
+                10
             }
+            false => 2,
+        };
 
-            stdout.flush()?;
+        let skip = blocks.len().saturating_sub(RECENT_BLOCKS_TO_DISPLAY);
+        for (lines, finalized) in blocks.into_iter().skip(skip) {
+            for (index, line) in lines.iter().enumerate() {
+                let i: u16 = u16::try_from(index).unwrap();
 
-            self.block_count = 0;
-            self.blockchain_display_column = 2;
-        }
+                stdout
+                    .queue(MoveTo(column, BLOCKCHAIN_DISPLAY_ROW + i + 1))?
+                    .queue(PrintStyledContent(styled_content(
+                        line.to_string(),
+                        finalized,
+                    )))?;
+            }
 
-        for (index, line) in lines.iter().enumerate() {
-            let i: u16 = u16::try_from(index).unwrap();
+            if finalized {
+                let i: u16 = u16::try_from(lines.len()).unwrap();
 
-            stdout
-                .queue(MoveTo(
-                    self.blockchain_display_column,
-                    BLOCKCHAIN_DISPLAY_ROW + i + 1,
-                ))?
-                .queue(PrintStyledContent(styled_content(line.to_string())))?;
+                stdout
+                    .queue(MoveTo(column + 8, BLOCKCHAIN_DISPLAY_ROW + i))?
+                    .queue(PrintStyledContent(FINALIZED_BLOCK_DISPLAY.clone()))?;
+            }
+
+            column += 18;
         }
 
+        stdout.queue(RestorePosition)?;
         stdout.flush()?;
 
-        self.block_count += 1;
-        self.blockchain_display_column += 18;
-
         Ok(())
     }
 }
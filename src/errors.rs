@@ -1,14 +1,49 @@
 use bincode;
 use rusty_leveldb::Status;
 
-#[derive(Debug, PartialEq)]
+#[derive(Clone, Debug, PartialEq)]
 pub enum Error {
     UnknownParentBlock,
     DuplicateBlock,
+    InvalidBlockHash,
+    InvalidBodyRoot,
+    InvalidHeight,
     InvalidProposer(String),
     InvalidSignature,
     InvalidWordset,
     InvalidWordsetLength,
+    WordsetTooShort,
+    WordsetTooLong,
+    DuplicateWord(String),
+    InvalidWordLength,
+    InvalidInitial,
+    SharedLetters,
+    NotAlphabetical,
+    ReusedWord(String),
+    UnusedWord(String),
+    WordCooldown(String, u64),
+    InsufficientWork(u32),
+    BlockIntervalTooShort(u64),
+    TooManyConsecutiveBlocks(u64),
+    TimestampBeforeParent,
+    TimestampBeforeMedian,
+    FutureBlock(i64),
+    InvalidAuthorityChange(String),
+    InvalidVote(String),
+    DuplicateVote,
+    FinalityConflict,
+
+    InvalidEnvelope(String),
+    MessageTooLarge(usize),
+    UnknownMessageType(u8),
+    UnsupportedEnvelopeVersion(u8),
+
+    KeyExists,
+    MissingKey,
+    InvalidPassword,
+    InvalidKeyFile(String),
+
+    InvalidChainSpec(String),
 
     StdError(String),
 
@@ -0,0 +1,192 @@
+use std::collections::{BTreeMap, HashMap};
+
+use libp2p::identity::ed25519::{Keypair, PublicKey};
+use serde::{Deserialize, Serialize};
+
+use crate::authority::AuthorityKey;
+use crate::block::BlockHash;
+use crate::errors::Error;
+use crate::spec::Finality;
+
+const CHECKPOINT_VOTE_DOMAIN: &[u8] = b"monkey/checkpoint-vote";
+
+/// A validator's signed statement that a checkpoint block is part of its
+/// canonical chain.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct CheckpointVote {
+    pub hash: BlockHash,
+    pub height: u64,
+    pub validator: AuthorityKey,
+    pub signature: Vec<u8>,
+}
+
+impl CheckpointVote {
+    pub fn new(hash: BlockHash, height: u64, keypair: &Keypair) -> Self {
+        let signature = keypair.sign(Self::message(&hash, height).as_bytes());
+
+        CheckpointVote {
+            hash: hash,
+            height: height,
+            validator: AuthorityKey::from(&keypair.public()),
+            signature: signature,
+        }
+    }
+
+    fn message(hash: &BlockHash, height: u64) -> BlockHash {
+        let mut encoded = CHECKPOINT_VOTE_DOMAIN.to_vec();
+        encoded.extend_from_slice(hash.as_bytes());
+        encoded.extend_from_slice(&height.to_be_bytes());
+
+        BlockHash::digest(&encoded)
+    }
+
+    pub fn verify_signature(&self) -> bool {
+        let message = Self::message(&self.hash, self.height);
+
+        PublicKey::decode(&self.validator.0)
+            .is_ok_and(|pk| pk.verify(message.as_bytes(), &self.signature))
+    }
+}
+
+/// Collects checkpoint votes until two thirds of the validators agree on a
+/// checkpoint.
+pub struct FinalityTracker {
+    validators: Vec<AuthorityKey>,
+    checkpoint_interval: u64,
+
+    // Votes on a checkpoint by validator, along with its height
+    votes: HashMap<BlockHash, (u64, BTreeMap<AuthorityKey, CheckpointVote>)>,
+}
+
+impl FinalityTracker {
+    pub fn new(finality: &Finality) -> Self {
+        FinalityTracker {
+            validators: finality.validators.clone(),
+            checkpoint_interval: finality.checkpoint_interval,
+            votes: HashMap::new(),
+        }
+    }
+
+    pub fn is_validator(&self, key: &AuthorityKey) -> bool {
+        self.validators.contains(key)
+    }
+
+    pub fn is_checkpoint(&self, height: u64) -> bool {
+        height > 0 && height.is_multiple_of(self.checkpoint_interval)
+    }
+
+    /// Checks that a vote is signed by a validator for a checkpoint height,
+    /// whether or not the block voted for is known.
+    pub fn check_vote(&self, vote: &CheckpointVote) -> Result<(), Error> {
+        if !self.is_validator(&vote.validator) {
+            return Err(Error::InvalidVote(format!(
+                "{} is not a validator",
+                vote.validator
+            )));
+        }
+
+        if !self.is_checkpoint(vote.height) {
+            return Err(Error::InvalidVote(format!(
+                "height {} is not a checkpoint",
+                vote.height
+            )));
+        }
+
+        if !vote.verify_signature() {
+            return Err(Error::InvalidSignature);
+        }
+
+        Ok(())
+    }
+
+    /// Counts a vote. Returns `true` once the checkpoint has been voted for
+    /// by at least two thirds of the validators.
+    pub fn add_vote(&mut self, vote: &CheckpointVote) -> Result<bool, Error> {
+        self.check_vote(vote)?;
+
+        let (_, voters) = self
+            .votes
+            .entry(vote.hash)
+            .or_insert_with(|| (vote.height, BTreeMap::new()));
+        if voters.contains_key(&vote.validator) {
+            return Err(Error::DuplicateVote);
+        }
+        voters.insert(vote.validator, vote.clone());
+
+        Ok(voters.len() * 3 >= self.validators.len() * 2)
+    }
+
+    /// Returns the votes counted on a checkpoint.
+    pub fn votes(&self, hash: &BlockHash) -> Vec<CheckpointVote> {
+        self.votes
+            .get(hash)
+            .map(|(_, voters)| voters.values().cloned().collect())
+            .unwrap_or_default()
+    }
+
+    /// Returns every vote counted and not pruned yet.
+    pub fn all_votes(&self) -> Vec<CheckpointVote> {
+        self.votes
+            .values()
+            .flat_map(|(_, voters)| voters.values().cloned())
+            .collect()
+    }
+
+    /// Forgets the votes on checkpoints up to a finalized height.
+    pub fn prune(&mut self, finalized_height: u64) {
+        self.votes
+            .retain(|_, (height, _)| *height > finalized_height);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_finality_tracker() {
+        let keypairs: Vec<Keypair> = (0..4).map(|_| Keypair::generate()).collect();
+        let mut tracker = FinalityTracker::new(&Finality {
+            validators: keypairs
+                .iter()
+                .map(|keypair| AuthorityKey::from(&keypair.public()))
+                .collect(),
+            checkpoint_interval: 5,
+        });
+        let checkpoint = BlockHash::digest(b"checkpoint");
+
+        assert!(!tracker.is_checkpoint(0));
+        assert!(!tracker.is_checkpoint(4));
+        assert!(tracker.is_checkpoint(10));
+
+        let vote = CheckpointVote::new(checkpoint, 10, &keypairs[0]);
+        assert_eq!(tracker.add_vote(&vote), Ok(false));
+        assert_eq!(tracker.add_vote(&vote), Err(Error::DuplicateVote));
+
+        assert!(matches!(
+            tracker.add_vote(&CheckpointVote::new(checkpoint, 9, &keypairs[1])),
+            Err(Error::InvalidVote(_))
+        ));
+        assert!(matches!(
+            tracker.add_vote(&CheckpointVote::new(checkpoint, 10, &Keypair::generate())),
+            Err(Error::InvalidVote(_))
+        ));
+
+        let mut forged = CheckpointVote::new(checkpoint, 10, &keypairs[1]);
+        forged.height = 15;
+        assert_eq!(tracker.add_vote(&forged), Err(Error::InvalidSignature));
+
+        // Three of four validators make two thirds
+        let vote = CheckpointVote::new(checkpoint, 10, &keypairs[1]);
+        assert_eq!(tracker.add_vote(&vote), Ok(false));
+        let vote = CheckpointVote::new(checkpoint, 10, &keypairs[2]);
+        assert_eq!(tracker.add_vote(&vote), Ok(true));
+
+        assert_eq!(tracker.votes(&checkpoint).len(), 3);
+
+        tracker.prune(10);
+        assert!(tracker.all_votes().is_empty());
+        let vote = CheckpointVote::new(checkpoint, 10, &keypairs[0]);
+        assert_eq!(tracker.add_vote(&vote), Ok(false));
+    }
+}
@@ -0,0 +1,246 @@
+use std::collections::BTreeSet;
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::path::{Path, PathBuf};
+
+use bincode::{deserialize, serialize};
+use chacha20poly1305::aead::{AeadInPlace, NewAead};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag};
+use hmac::Hmac;
+use libp2p::identity::{ed25519, Keypair};
+use pbkdf2::pbkdf2;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::errors::Error;
+
+const KEY_FILE: &str = "node.key";
+const VOTES_FILE: &str = "votes";
+const PBKDF2_ROUNDS: usize = 100_000;
+
+#[derive(Serialize, Deserialize)]
+enum KeyFile {
+    Plain(Vec<u8>),
+    Encrypted {
+        salt: [u8; 16],
+        nonce: [u8; 12],
+        ciphertext: Vec<u8>,
+        tag: [u8; 16],
+    },
+}
+
+/// ChaCha20-Poly1305 keyed with PBKDF2-HMAC-SHA256 of the password.
+fn cipher(password: &str, salt: &[u8]) -> ChaCha20Poly1305 {
+    let mut key = Key::default();
+    pbkdf2::<Hmac<Sha256>>(password.as_bytes(), salt, PBKDF2_ROUNDS, &mut key);
+
+    ChaCha20Poly1305::new(&key)
+}
+
+fn key_path(dir: &Path) -> PathBuf {
+    dir.join(KEY_FILE)
+}
+
+/// Creates the key file readable by the owner only, since a plain key file
+/// holds the secret unencrypted.
+fn write_key_file(path: &Path, contents: &[u8]) -> Result<(), Error> {
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    options.mode(0o600);
+
+    options.open(path)?.write_all(contents)?;
+
+    Ok(())
+}
+
+/// Stores a new node key in `dir`, encrypted if a password is given.
+/// An existing key is never overwritten.
+pub fn generate(dir: &Path, password: Option<&str>) -> Result<Keypair, Error> {
+    let path = key_path(dir);
+    if path.exists() {
+        return Err(Error::KeyExists);
+    }
+
+    let keypair = ed25519::Keypair::generate();
+    let secret = keypair.encode().to_vec();
+
+    let key_file = match password {
+        Some(password) => {
+            let mut salt = [0u8; 16];
+            let mut nonce = [0u8; 12];
+            rand::thread_rng().fill_bytes(&mut salt);
+            rand::thread_rng().fill_bytes(&mut nonce);
+
+            let mut ciphertext = secret;
+            let tag = cipher(password, &salt)
+                .encrypt_in_place_detached(&Nonce::from(nonce), &[], &mut ciphertext)
+                .map_err(|_| Error::StdError("failed to encrypt the node key".into()))?;
+
+            KeyFile::Encrypted {
+                salt: salt,
+                nonce: nonce,
+                ciphertext: ciphertext,
+                tag: tag.into(),
+            }
+        }
+        None => KeyFile::Plain(secret),
+    };
+
+    fs::create_dir_all(dir)?;
+    write_key_file(&path, &serialize(&key_file)?)?;
+
+    Ok(Keypair::Ed25519(keypair))
+}
+
+/// Whether the node key stored in `dir` needs a password to be loaded.
+pub fn is_encrypted(dir: &Path) -> bool {
+    let key_file = fs::read(key_path(dir))
+        .ok()
+        .and_then(|bytes| deserialize(&bytes).ok());
+
+    matches!(key_file, Some(KeyFile::Encrypted { .. }))
+}
+
+/// Loads the node key stored in `dir`.
+pub fn load(dir: &Path, password: Option<&str>) -> Result<Keypair, Error> {
+    let path = key_path(dir);
+    if !path.exists() {
+        return Err(Error::MissingKey);
+    }
+
+    let mut secret = match deserialize(&fs::read(&path)?)? {
+        KeyFile::Plain(secret) => secret,
+        KeyFile::Encrypted {
+            salt,
+            nonce,
+            ciphertext,
+            tag,
+        } => {
+            let password = password.ok_or(Error::InvalidPassword)?;
+
+            let mut secret = ciphertext;
+            cipher(password, &salt)
+                .decrypt_in_place_detached(&Nonce::from(nonce), &[], &mut secret, &Tag::from(tag))
+                .map_err(|_| Error::InvalidPassword)?;
+
+            secret
+        }
+    };
+
+    match ed25519::Keypair::decode(&mut secret) {
+        Ok(keypair) => Ok(Keypair::Ed25519(keypair)),
+        Err(e) => Err(Error::InvalidKeyFile(e.to_string())),
+    }
+}
+
+/// Loads the node key stored in `dir`, generating one on first start.
+pub fn load_or_generate(dir: &Path, password: Option<&str>) -> Result<Keypair, Error> {
+    match load(dir, password) {
+        Err(Error::MissingKey) => {
+            info!("Generating a new node key in {:?}", dir);
+
+            generate(dir, password)
+        }
+        result => result,
+    }
+}
+
+/// Checkpoint heights the node key voted at, kept next to the key so that
+/// a restarted validator never signs two votes for the same height.
+pub struct VoteRecord {
+    path: PathBuf,
+    heights: BTreeSet<u64>,
+}
+
+impl VoteRecord {
+    pub fn load(dir: &Path) -> Result<Self, Error> {
+        let path = dir.join(VOTES_FILE);
+        let heights = match path.exists() {
+            true => deserialize(&fs::read(&path)?)?,
+            false => BTreeSet::new(),
+        };
+
+        Ok(VoteRecord {
+            path: path,
+            heights: heights,
+        })
+    }
+
+    /// Records a vote at `height` before it is signed. Returns `false` if
+    /// the key voted at that height already.
+    pub fn record(&mut self, height: u64) -> Result<bool, Error> {
+        if !self.heights.insert(height) {
+            return Ok(false);
+        }
+
+        // Renamed into place so that a crash never leaves half a record
+        let temp = self.path.with_extension("tmp");
+        fs::write(&temp, serialize(&self.heights)?)?;
+        fs::rename(&temp, &self.path)?;
+
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_dir(name: &str) -> PathBuf {
+        let dir = Path::new(".data").join(".test").join(name);
+        let _ = fs::remove_dir_all(&dir);
+
+        dir
+    }
+
+    #[test]
+    fn test_plain_key() {
+        let dir = key_dir("keystore_plain");
+
+        let keypair = generate(&dir, None).unwrap();
+        assert_eq!(generate(&dir, None).err().unwrap(), Error::KeyExists);
+
+        let loaded = load(&dir, None).unwrap();
+        assert_eq!(loaded.public(), keypair.public());
+        assert_eq!(
+            load_or_generate(&dir, None).unwrap().public(),
+            keypair.public()
+        );
+    }
+
+    #[test]
+    fn test_encrypted_key() {
+        let dir = key_dir("keystore_encrypted");
+        assert_eq!(load(&dir, None).err().unwrap(), Error::MissingKey);
+
+        let keypair = generate(&dir, Some("banana")).unwrap();
+
+        let loaded = load(&dir, Some("banana")).unwrap();
+        assert_eq!(loaded.public(), keypair.public());
+
+        assert_eq!(load(&dir, None).err().unwrap(), Error::InvalidPassword);
+        assert_eq!(
+            load(&dir, Some("apple")).err().unwrap(),
+            Error::InvalidPassword
+        );
+    }
+
+    #[test]
+    fn test_vote_record() {
+        let dir = key_dir("keystore_votes");
+        fs::create_dir_all(&dir).unwrap();
+
+        let mut votes = VoteRecord::load(&dir).unwrap();
+        assert_eq!(votes.record(10), Ok(true));
+        assert_eq!(votes.record(10), Ok(false));
+
+        // Remembered across restarts
+        let mut votes = VoteRecord::load(&dir).unwrap();
+        assert_eq!(votes.record(10), Ok(false));
+        assert_eq!(votes.record(20), Ok(true));
+    }
+}
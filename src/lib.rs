@@ -4,14 +4,21 @@ extern crate lazy_static;
 #[macro_use]
 extern crate log;
 
+pub mod authority;
 mod behaviour;
 mod block;
+mod chain;
 mod dictionary;
 mod display;
 mod errors;
+mod finality;
+pub mod keystore;
+mod merkle;
 mod service;
+pub mod spec;
 mod store;
 
+pub use behaviour::types::DEFAULT_NETWORK;
 pub use block::Block;
 pub use errors::Error;
-pub use service::Service;
+pub use service::{Service, DEFAULT_MAX_FUTURE_DRIFT};
@@ -0,0 +1,139 @@
+use serde::{Deserialize, Serialize};
+
+use crate::block::BlockHash;
+
+// Domain separation between leaves and inner nodes, so that an inner node
+// can never be passed off as a leaf
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+pub fn leaf_hash(data: &[u8]) -> BlockHash {
+    let mut encoded = Vec::with_capacity(data.len() + 1);
+    encoded.push(LEAF_PREFIX);
+    encoded.extend_from_slice(data);
+
+    BlockHash::digest(&encoded)
+}
+
+fn node_hash(left: &BlockHash, right: &BlockHash) -> BlockHash {
+    let mut encoded = Vec::with_capacity(65);
+    encoded.push(NODE_PREFIX);
+    encoded.extend_from_slice(left.as_bytes());
+    encoded.extend_from_slice(right.as_bytes());
+
+    BlockHash::digest(&encoded)
+}
+
+fn next_level(level: &[BlockHash]) -> Vec<BlockHash> {
+    level
+        .chunks(2)
+        .map(|pair| match pair {
+            [left, right] => node_hash(left, right),
+            [single] => *single,
+            _ => unreachable!(),
+        })
+        .collect()
+}
+
+/// Computes the Merkle root over the given leaf hashes. A node without a
+/// sibling is promoted to the next level unchanged.
+pub fn root(leaves: &[BlockHash]) -> BlockHash {
+    if leaves.is_empty() {
+        return BlockHash::digest(&[]);
+    }
+
+    let mut level = leaves.to_vec();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    level[0]
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+struct ProofNode {
+    hash: BlockHash,
+    is_left: bool,
+}
+
+/// Inclusion proof of a single leaf, listing the sibling hashes on the
+/// path from the leaf up to the root.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MerkleProof {
+    path: Vec<ProofNode>,
+}
+
+impl MerkleProof {
+    pub fn new(leaves: &[BlockHash], index: usize) -> Option<Self> {
+        if index >= leaves.len() {
+            return None;
+        }
+
+        let mut path = Vec::new();
+        let mut index = index;
+        let mut level = leaves.to_vec();
+        while level.len() > 1 {
+            let sibling = index ^ 1;
+            if sibling < level.len() {
+                path.push(ProofNode {
+                    hash: level[sibling],
+                    is_left: sibling < index,
+                });
+            }
+
+            level = next_level(&level);
+            index /= 2;
+        }
+
+        Some(MerkleProof { path })
+    }
+
+    pub fn verify(&self, root: &BlockHash, leaf: &BlockHash) -> bool {
+        let computed = self
+            .path
+            .iter()
+            .fold(*leaf, |acc, node| match node.is_left {
+                true => node_hash(&node.hash, &acc),
+                false => node_hash(&acc, &node.hash),
+            });
+
+        computed == *root
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn leaves(count: usize) -> Vec<BlockHash> {
+        (0..count)
+            .map(|i| leaf_hash(&(i as u32).to_be_bytes()))
+            .collect()
+    }
+
+    #[test]
+    fn test_root() {
+        let l = leaves(3);
+
+        assert_eq!(root(&l[..1]), l[0]);
+        assert_eq!(root(&l[..2]), node_hash(&l[0], &l[1]));
+        assert_eq!(root(&l), node_hash(&node_hash(&l[0], &l[1]), &l[2]));
+    }
+
+    #[test]
+    fn test_proof() {
+        for count in 1..10 {
+            let l = leaves(count);
+            let r = root(&l);
+
+            for (index, leaf) in l.iter().enumerate() {
+                let proof = MerkleProof::new(&l, index).unwrap();
+
+                assert!(proof.verify(&r, leaf));
+                assert!(!proof.verify(&r, &leaf_hash(b"notaleaf")));
+            }
+
+            assert!(MerkleProof::new(&l, count).is_none());
+        }
+    }
+}
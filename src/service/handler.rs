@@ -1,52 +1,139 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
+use std::time::{Duration as StdDuration, Instant};
 
-use bincode::serialize;
+use bincode::deserialize;
+use chrono::{Duration, Utc};
 use libp2p::{gossipsub::MessageId, identity::PublicKey, PeerId};
 use tokio::{
     runtime::Handle,
     sync::mpsc::{self, UnboundedSender},
 };
 
-use crate::behaviour::types::GossipsubMessage;
-use crate::block::{Block, SignedBlock};
-use crate::display::Display;
+use crate::authority::AuthorityChange;
+use crate::behaviour::types::{GossipsubMessage, MessageAcceptance, SyncRequest, SyncResponse};
+use crate::block::{Block, BlockHash, SignedBlock};
+use crate::chain::{ChainEvent, ChainState};
+use crate::display::{Display, RECENT_BLOCKS_TO_DISPLAY};
 use crate::errors::Error;
+use crate::finality::CheckpointVote;
+use crate::merkle::MerkleProof;
+use crate::service::orphans::OrphanPool;
+use crate::service::scores::Misbehaviour;
 use crate::service::ServiceMessage;
+use crate::spec::ChainSpec;
 use crate::store::DiscStore;
 
+const SYNC_BATCH_SIZE: u64 = 64;
+const SYNC_REQUEST_TIMEOUT: StdDuration = StdDuration::from_secs(10);
+
+// Blocks further ahead of the allowed drift are dropped instead of held
+const MAX_FUTURE_BLOCK_HOLD: i64 = 300;
+const MAX_FUTURE_BLOCKS: usize = 64;
+
 pub struct Handler {
+    rt_handle: Handle,
     store: Arc<DiscStore>,
+    spec: ChainSpec,
+    chain: ChainState,
+
+    // Seconds a block may be timestamped ahead of the local clock before
+    // it is held back, a setting of the node rather than of the chain
+    max_future_drift: u64,
     service_send: UnboundedSender<ServiceMessage>,
-    display: Display,
+    orphans: OrphanPool<SignedBlock>,
+    orphan_votes: OrphanPool<CheckpointVote>,
+    sync: Option<SyncState>,
+
+    // Outstanding sync requests of ours by id, along with the peer asked
+    // and the deadline for its response
+    requests: HashMap<u64, (PeerId, Instant)>,
+    next_request_id: u64,
+
+    // Blocks too far ahead of the local clock along with the peer they came
+    // from and their gossip message id, retried as time passes
+    future_blocks: HashMap<BlockHash, (SignedBlock, PeerId, Option<MessageId>)>,
+
+    // Set to stop the block being mined in the background
+    mining: Option<Arc<AtomicBool>>,
+
+    // Included in the next block of ours
+    authority_change: Option<AuthorityChange>,
+}
+
+/// Progress of the sync with a peer whose chain weighs more than ours.
+struct SyncState {
+    peer_id: PeerId,
+    /// Head announced by the peer, the sync is complete once it is imported.
+    head: BlockHash,
+    /// Request whose response moves the sync forward, either a range of
+    /// blocks or the parent of a block the range builds on.
+    request: u64,
+    /// Start of the next range to request.
+    next_height: u64,
 }
 
 #[derive(Debug)]
 pub enum HandlerMessage {
     Publish(MessageId, PeerId, GossipsubMessage),
     OwnBlock(SignedBlock),
+    OwnVote(CheckpointVote),
     Stdin(String, PublicKey),
+    AuthorityChange(AuthorityChange),
+    ProveWord(String),
+    SyncStart(PeerId, BlockHash, u64),
+    SyncRequest(PeerId, u64, SyncRequest),
+    SyncResponse(PeerId, u64, SyncResponse),
+    SyncStop(PeerId),
+    /// A compatible peer sent its status.
+    PeerConnected(PeerId),
+    Listening,
+    Tick,
 }
 
 impl Handler {
     pub fn new(
         rt_handle: &Handle,
         store: &Arc<DiscStore>,
+        spec: ChainSpec,
+        max_future_drift: u64,
         service_send: UnboundedSender<ServiceMessage>,
     ) -> UnboundedSender<HandlerMessage> {
         let (handler_send, mut handler_recv) = mpsc::unbounded_channel::<HandlerMessage>();
 
-        let display = Display::new();
+        if let Err(e) = Self::import_genesis(store) {
+            panic!("Error inserting genesis block: {:?}", e)
+        }
+
+        let chain = match ChainState::new(store, &spec) {
+            Ok(chain) => chain,
+            Err(e) => panic!("Error loading chain state: {:?}", e),
+        };
+
+        let head =
+            ServiceMessage::HeadChanged(chain.head(), chain.head_height(), chain.head_weight());
+        if let Err(e) = service_send.send(head) {
+            error!("Error sending message between Handler and Service: {:?}", e);
+        }
 
         let mut handler = Handler {
+            rt_handle: rt_handle.clone(),
             service_send: service_send,
             store: Arc::clone(store),
-            display: display,
+            spec: spec,
+            chain: chain,
+            max_future_drift: max_future_drift,
+            orphans: OrphanPool::new(),
+            orphan_votes: OrphanPool::new(),
+            sync: None,
+            requests: HashMap::new(),
+            next_request_id: 0,
+            future_blocks: HashMap::new(),
+            mining: None,
+            authority_change: None,
         };
 
-        if let Err(e) = handler.import_genesis() {
-            panic!("Error inserting genesis block: {:?}", e)
-        }
-
         rt_handle.spawn_blocking(move || loop {
             match handler_recv.try_recv() {
                 Ok(handler_msg) => handler.handle_message(handler_msg),
@@ -70,16 +157,18 @@ impl Handler {
                     .map(|w| w.to_lowercase())
                     .collect();
 
-                // TODO: this will be removed once we have a state
-                // that maintains the longest chain and parent_hash
-                // is the block hash of the last inserted block
-                let (dummy_parent_hash, _, _) = Block::genesis_block();
-                match Block::new(wordlist, proposer, dummy_parent_hash) {
-                    Ok(block) => {
-                        if let Err(e) = self.service_send.send(ServiceMessage::NewBlock(block)) {
-                            error!("Error sending message between Handler and Service: {:?}", e);
-                        }
-                    }
+                let height = self.chain.head_height() + 1;
+                let authority_change = self.authority_change.take();
+                let block = Block::new(wordlist, proposer, self.chain.head(), height, &self.spec)
+                    .map(|block| match authority_change {
+                        Some(change) => block.with_authority_change(change),
+                        None => block,
+                    })
+                    .and_then(|block| self.chain.check_words(&block).map(|_| block))
+                    .and_then(|block| self.chain.check_proposer(&block).map(|_| block))
+                    .and_then(|block| self.chain.check_authority(&block).map(|_| block));
+                match block.and_then(|block| self.propose(block)) {
+                    Ok(()) => {}
                     Err(e) => {
                         warn!("Invalid block: {:?}", e);
 
@@ -87,63 +176,752 @@ impl Handler {
                     }
                 }
             }
+            HandlerMessage::AuthorityChange(change) => {
+                info!(
+                    "Including authority change {} in the next block",
+                    change.sequence
+                );
+
+                self.authority_change = Some(change);
+            }
+            HandlerMessage::ProveWord(word) => match self.prove_word(&word) {
+                Ok((hash, height, proof)) => {
+                    info!(
+                        "Word {} is in block {:?} at height {}, proof {:?}",
+                        word, hash, height, proof
+                    );
+
+                    Display::notice_word_proof(&word, height).unwrap();
+                }
+                Err(e) => warn!("Cannot prove word {}: {:?}", word, e),
+            },
             HandlerMessage::OwnBlock(signed_block) => match self.import_block(&signed_block) {
                 Ok(()) => {
                     info!("Inserted own block {:?}", signed_block.message.hash);
 
-                    self.display.new_block(signed_block.display()).unwrap();
                     Display::notice_valid_block().unwrap();
                 }
                 Err(e) => warn!("Ignoring invalid own block: {:?}", e),
             },
+            HandlerMessage::OwnVote(vote) => match self.import_vote(&vote) {
+                Ok(()) => info!("Voted for checkpoint {:?}", vote.hash),
+                Err(e) => warn!("Ignoring own vote: {:?}", e),
+            },
             HandlerMessage::Publish(id, source, msg) => match msg {
-                GossipsubMessage::Block(signed_block) => match self.import_block(&signed_block) {
-                    Ok(()) => {
-                        info!("Inserted published block {:?}", signed_block.message.hash);
+                GossipsubMessage::Block(signed_block) => {
+                    let result = self.import_peer_block(&signed_block, &source, Some(id.clone()));
+                    match &result {
+                        Ok(()) => {
+                            info!("Inserted published block {:?}", signed_block.message.hash)
+                        }
+                        Err(Error::UnknownParentBlock) => {
+                            info!("Holding orphan block {:?}", signed_block.message.hash)
+                        }
+                        Err(Error::DuplicateBlock) => {
+                            debug!("Ignoring known block {:?}", signed_block.message.hash)
+                        }
+                        Err(e) => warn!("Ignoring invalid published block: {:?}", e),
+                    }
 
-                        if let Err(e) = self
-                            .service_send
-                            .send(ServiceMessage::PropagateGossip(id, source))
-                        {
-                            error!("Error sending message between Handler and Service: {:?}", e);
+                    let acceptance = Self::acceptance(result);
+                    self.send_to_service(ServiceMessage::ValidationResult(id, source, acceptance));
+                }
+                GossipsubMessage::Vote(vote) => {
+                    let result = self.import_vote(&vote);
+                    match &result {
+                        Ok(()) => info!("Counted vote of {} for {:?}", vote.validator, vote.hash),
+                        Err(Error::UnknownParentBlock) => {
+                            debug!("Holding vote for unknown block {:?}", vote.hash);
+
+                            self.orphan_votes.insert(
+                                vote.clone(),
+                                source.clone(),
+                                Some(id.clone()),
+                            );
+                        }
+                        Err(Error::DuplicateVote) => {
+                            debug!("Ignoring known vote for {:?}", vote.hash)
                         }
+                        Err(e) => warn!("Ignoring invalid vote: {:?}", e),
                     }
-                    Err(e) => warn!("Ignoring invalid published block: {:?}", e),
-                },
+
+                    let acceptance = Self::acceptance(result);
+                    self.send_to_service(ServiceMessage::ValidationResult(id, source, acceptance));
+                }
             },
+            HandlerMessage::SyncStart(peer_id, head, height) => {
+                info!("Starting sync with {}", peer_id);
+
+                // A heavier chain that is not longer than ours is fetched
+                // backwards from its head
+                let next_height = self.chain.head_height() + 1;
+                let request = match height >= next_height {
+                    true => SyncRequest::BlocksByRange {
+                        start: next_height,
+                        count: SYNC_BATCH_SIZE,
+                    },
+                    false => SyncRequest::BlocksByHash(vec![head]),
+                };
+
+                let request = self.send_sync_request(peer_id.clone(), request);
+                self.sync = Some(SyncState {
+                    peer_id: peer_id,
+                    head: head,
+                    request: request,
+                    next_height: next_height,
+                });
+            }
+            HandlerMessage::SyncStop(peer_id) => {
+                if self
+                    .sync
+                    .as_ref()
+                    .is_some_and(|sync| sync.peer_id == peer_id)
+                {
+                    self.sync = None;
+                }
+            }
+            HandlerMessage::SyncRequest(peer_id, id, request) => {
+                let response = self.handle_sync_request(request);
+
+                self.send_to_service(ServiceMessage::SyncRespond(peer_id, id, response));
+            }
+            HandlerMessage::PeerConnected(peer_id) => {
+                // Votes are only gossiped live, a node that missed them
+                // learns what the peer finalized from the proof
+                if self.spec.finality.is_some() {
+                    self.send_sync_request(peer_id, SyncRequest::FinalityProof);
+                }
+            }
+            HandlerMessage::Listening => {
+                let rules = self.spec.rules_at(self.chain.head_height() + 1);
+
+                Display::init(rules).unwrap();
+            }
+            HandlerMessage::Tick => {
+                self.orphan_votes.prune(Instant::now());
+                self.import_future_blocks();
+                self.expire_requests(Instant::now());
+            }
+            HandlerMessage::SyncResponse(peer_id, id, response) => {
+                match self.requests.remove(&id) {
+                    Some((requested_from, _)) if requested_from == peer_id => {}
+                    _ => {
+                        debug!("Ignoring unexpected sync response {} from {}", id, peer_id);
+
+                        return;
+                    }
+                }
+
+                let blocks = match response {
+                    SyncResponse::Blocks(blocks) => blocks,
+                    SyncResponse::FinalityProof(votes) => {
+                        self.import_finality_proof(peer_id, votes);
+
+                        return;
+                    }
+                };
+
+                match self.sync.as_ref().is_some_and(|sync| sync.request == id) {
+                    true => self.handle_sync_blocks(peer_id, blocks),
+                    false => {
+                        for signed_block in blocks {
+                            if let Err(e) = self.import_peer_block(&signed_block, &peer_id, None) {
+                                self.report_import_error(&peer_id, e);
+                            }
+                        }
+                    }
+                }
+            }
+        };
+    }
+
+    /// Maps the outcome of importing a gossiped block to a verdict on the
+    /// message that carried it.
+    fn acceptance(result: Result<(), Error>) -> MessageAcceptance {
+        match result {
+            Ok(()) => MessageAcceptance::Accept,
+            // Orphans cannot be checked against their parent yet
+            Err(Error::DuplicateBlock) | Err(Error::UnknownParentBlock) => {
+                MessageAcceptance::Ignore
+            }
+            // Votes travel the same gossip routes as the blocks voted on
+            Err(Error::DuplicateVote) => MessageAcceptance::Ignore,
+            // Built before the peer saw the votes finalizing another branch
+            Err(Error::FinalityConflict) => MessageAcceptance::Ignore,
+            // Possibly our clock that is behind
+            Err(Error::FutureBlock(_)) => MessageAcceptance::Ignore,
+            // Local failures say nothing about the block
+            Err(Error::StdError(_)) | Err(Error::DBError(_)) | Err(Error::SerdeError(_)) => {
+                MessageAcceptance::Ignore
+            }
+            // Peer scores penalise the same errors
+            Err(e) => MessageAcceptance::Reject(e),
+        }
+    }
+
+    /// Hands a block of ours to the service to be signed and published,
+    /// mining it on a background task first if the chain needs proof of
+    /// work. Mining a new block stops mining the previous one.
+    fn propose(&mut self, mut block: Block) -> Result<(), Error> {
+        let difficulty = self.chain.next_difficulty(&block.header.parent_hash)?;
+        if difficulty == 0 {
+            self.send_to_service(ServiceMessage::NewBlock(block));
+
+            return Ok(());
+        }
+
+        let cancel = Arc::new(AtomicBool::new(false));
+        if let Some(previous) = self.mining.replace(Arc::clone(&cancel)) {
+            previous.store(true, Ordering::Relaxed);
+        }
+
+        info!("Mining block at difficulty {}", difficulty);
+
+        let service_send = self.service_send.clone();
+        self.rt_handle.spawn_blocking(move || {
+            if !block.mine(difficulty, &cancel) {
+                debug!("Stopped mining block at height {}", block.header.height);
+
+                return;
+            }
+
+            info!(
+                "Mined block {:?} with nonce {}",
+                block.hash, block.header.nonce
+            );
+
+            if let Err(e) = service_send.send(ServiceMessage::NewBlock(block)) {
+                error!("Error sending message between Handler and Service: {:?}", e);
+            }
+        });
+
+        Ok(())
+    }
+
+    fn send_to_service(&self, service_msg: ServiceMessage) {
+        if let Err(e) = self.service_send.send(service_msg) {
+            error!("Error sending message between Handler and Service: {:?}", e);
+        }
+    }
+
+    fn send_sync_request(&mut self, peer_id: PeerId, request: SyncRequest) -> u64 {
+        let id = self.next_request_id;
+        self.next_request_id += 1;
+
+        let deadline = Instant::now() + SYNC_REQUEST_TIMEOUT;
+        self.requests.insert(id, (peer_id.clone(), deadline));
+        self.send_to_service(ServiceMessage::SyncRequest(peer_id, id, request));
+
+        id
+    }
+
+    /// Imports a response to the request driving the sync. A range that
+    /// builds on a block we do not know is held in the orphan pool while
+    /// the missing parent is fetched. The sync only carries on while it
+    /// makes progress, and is given up on the first invalid block.
+    fn handle_sync_blocks(&mut self, peer_id: PeerId, blocks: Vec<SignedBlock>) {
+        let mut imported = 0;
+        let mut parent_request = None;
+        let mut next_height = self.sync.as_ref().unwrap().next_height;
+        let start_height = next_height;
+
+        for signed_block in blocks {
+            let hash = signed_block.message.hash;
+            let height = signed_block.message.header.height;
+
+            match self.import_block(&signed_block) {
+                Ok(()) => imported += 1 + self.import_orphans(hash),
+                Err(Error::DuplicateBlock) => {}
+                Err(Error::FutureBlock(ahead)) => {
+                    self.hold_future_block(&signed_block, &peer_id, None, ahead);
+                }
+                Err(Error::UnknownParentBlock) => {
+                    if parent_request.is_none() {
+                        let parent_hash = signed_block.message.header.parent_hash;
+                        debug!(
+                            "Requesting missing parent {:?} from {}",
+                            parent_hash, peer_id
+                        );
+
+                        let request = SyncRequest::BlocksByHash(vec![parent_hash]);
+                        parent_request = Some(self.send_sync_request(peer_id.clone(), request));
+                    }
+
+                    self.orphans.insert(signed_block, peer_id.clone(), None);
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid synced block: {:?}", e);
+
+                    self.report_import_error(&peer_id, e);
+                    self.fail_sync();
+
+                    return;
+                }
+            }
+
+            next_height = next_height.max(height + 1);
+        }
+
+        let head = self.sync.as_ref().unwrap().head;
+        if self.store.get(head.as_bytes()).is_some() {
+            info!("Sync complete at height {}", self.chain.head_height());
+
+            self.sync = None;
+            self.send_to_service(ServiceMessage::SyncComplete(peer_id));
+        } else if let Some(request) = parent_request {
+            let sync = self.sync.as_mut().unwrap();
+            sync.request = request;
+            sync.next_height = next_height;
+        } else if imported > 0 || next_height > start_height {
+            let request = SyncRequest::BlocksByRange {
+                start: next_height,
+                count: SYNC_BATCH_SIZE,
+            };
+
+            let request = self.send_sync_request(peer_id, request);
+            let sync = self.sync.as_mut().unwrap();
+            sync.request = request;
+            sync.next_height = next_height;
+        } else {
+            debug!("Sync with {} made no progress", peer_id);
+
+            self.fail_sync();
+        }
+    }
+
+    /// Counts the votes a peer finalized its finalized block with. Votes on
+    /// a block we do not know are held while the block is fetched.
+    fn import_finality_proof(&mut self, peer_id: PeerId, votes: Vec<CheckpointVote>) {
+        for vote in votes {
+            match self.import_vote(&vote) {
+                Ok(()) => info!("Counted vote of {} for {:?}", vote.validator, vote.hash),
+                Err(Error::DuplicateVote) => {}
+                Err(Error::UnknownParentBlock) => {
+                    let hash = vote.hash;
+                    if self.orphan_votes.insert(vote, peer_id.clone(), None) {
+                        debug!("Requesting finalized block {:?} from {}", hash, peer_id);
+
+                        let request = SyncRequest::BlocksByHash(vec![hash]);
+                        self.send_sync_request(peer_id.clone(), request);
+                    }
+                }
+                Err(e) => {
+                    warn!("Ignoring invalid finality proof: {:?}", e);
+
+                    self.report_import_error(&peer_id, e);
+
+                    return;
+                }
+            }
+        }
+    }
+
+    /// Gives up on the sync peer, the service moves on to another one.
+    fn fail_sync(&mut self) {
+        if let Some(sync) = self.sync.take() {
+            self.send_to_service(ServiceMessage::SyncFailed(sync.peer_id));
+        }
+    }
+
+    /// Forgets the requests whose deadline passed, penalising the sync peer
+    /// if it did not answer the request the sync waits for.
+    fn expire_requests(&mut self, now: Instant) {
+        let expired: Vec<u64> = self
+            .requests
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(id, _)| *id)
+            .collect();
+
+        for id in expired {
+            let (peer_id, _) = self.requests.remove(&id).unwrap();
+
+            if self.sync.as_ref().is_some_and(|sync| sync.request == id) {
+                warn!("Sync request {} to {} timed out", id, peer_id);
+
+                self.send_to_service(ServiceMessage::ReportPeer(
+                    peer_id,
+                    Misbehaviour::SyncTimeout,
+                ));
+                self.fail_sync();
+            }
+        }
+    }
+
+    /// Reports the peer that sent a block failing to import, unless the
+    /// failure says nothing about the peer.
+    fn report_import_error(&self, peer_id: &PeerId, e: Error) {
+        if let MessageAcceptance::Reject(e) = Self::acceptance(Err(e)) {
+            self.send_to_service(ServiceMessage::ReportPeer(
+                peer_id.clone(),
+                Misbehaviour::InvalidBlock(e),
+            ));
+        }
+    }
+
+    fn handle_sync_request(&self, request: SyncRequest) -> SyncResponse {
+        let hashes = match request {
+            SyncRequest::FinalityProof => {
+                let votes = self.chain.finality_proof().unwrap_or_else(|e| {
+                    error!("Failed to load finality proof: {:?}", e);
+
+                    Vec::new()
+                });
+
+                return SyncResponse::FinalityProof(votes);
+            }
+            SyncRequest::BlocksByHash(hashes) => hashes,
+            SyncRequest::BlocksByRange { start, count } => (start..)
+                .take(count.min(SYNC_BATCH_SIZE) as usize)
+                .map_while(|height| self.chain.canonical_hash(height))
+                .collect(),
         };
+
+        let blocks = hashes
+            .iter()
+            .take(SYNC_BATCH_SIZE as usize)
+            .filter_map(|hash| self.store.get(hash.as_bytes()))
+            .filter_map(|bytes| deserialize::<SignedBlock>(&bytes).ok())
+            .collect();
+
+        SyncResponse::Blocks(blocks)
     }
 
-    fn import_genesis(&self) -> Result<(), Error> {
+    fn import_genesis(store: &DiscStore) -> Result<(), Error> {
         let (_, genesis_block_hash, genesis_block) = Block::genesis_block();
 
-        self.store.put(&genesis_block_hash, &genesis_block)?;
+        store.put(&genesis_block_hash, &genesis_block)?;
 
         Ok(())
     }
 
-    fn import_block(&self, signed_block: &SignedBlock) -> Result<(), Error> {
-        signed_block.message.clone().validate()?;
+    fn import_block(&mut self, signed_block: &SignedBlock) -> Result<(), Error> {
+        signed_block.message.clone().validate(&self.spec)?;
 
         match signed_block.verify_signature() {
             true => {
-                let block_hash = signed_block.message.hash.to_be_bytes();
-                let parent_hash = signed_block.message.parent_hash.to_be_bytes();
-
-                if let None = self.store.get(&parent_hash) {
-                    return Err(Error::UnknownParentBlock);
+                let ahead = (signed_block.message.header.timestamp - Utc::now()).num_seconds();
+                if ahead > self.max_future_drift as i64 {
+                    return Err(Error::FutureBlock(ahead));
                 }
 
-                if let Some(_) = self.store.get(&block_hash) {
-                    return Err(Error::DuplicateBlock);
+                if let Some(event) = self.chain.insert(signed_block)? {
+                    self.handle_chain_event(event);
                 }
 
-                let signed_block_bytes = serialize(&signed_block).unwrap();
-                self.store.put(&block_hash, &signed_block_bytes)?;
+                self.import_orphan_votes(signed_block.message.hash);
 
                 Ok(())
             }
             false => Err(Error::InvalidSignature),
         }
     }
+
+    /// Imports a block received from `source`. A block whose parent is not
+    /// known yet is held in the orphan pool while the parent is fetched from
+    /// the same peer, a block too far ahead of the local clock is held until
+    /// it is due, and any orphans waiting on an imported block are imported
+    /// right after it.
+    fn import_peer_block(
+        &mut self,
+        signed_block: &SignedBlock,
+        source: &PeerId,
+        message_id: Option<MessageId>,
+    ) -> Result<(), Error> {
+        match self.import_block(signed_block) {
+            Ok(()) => {
+                self.import_orphans(signed_block.message.hash);
+
+                Ok(())
+            }
+            Err(Error::UnknownParentBlock) => {
+                if self
+                    .orphans
+                    .insert(signed_block.clone(), source.clone(), message_id)
+                {
+                    let parent_hash = signed_block.message.header.parent_hash;
+                    debug!(
+                        "Requesting missing parent {:?} from {}",
+                        parent_hash, source
+                    );
+
+                    let request = SyncRequest::BlocksByHash(vec![parent_hash]);
+                    self.send_sync_request(source.clone(), request);
+                }
+
+                Err(Error::UnknownParentBlock)
+            }
+            Err(Error::FutureBlock(ahead)) => {
+                self.hold_future_block(signed_block, source, message_id, ahead);
+
+                Err(Error::FutureBlock(ahead))
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Builds an inclusion proof for the latest canonical use of `word`,
+    /// checked against the header of the block it is in.
+    fn prove_word(&mut self, word: &str) -> Result<(BlockHash, u64, MerkleProof), Error> {
+        let unused = || Error::UnusedWord(word.to_string());
+
+        let height = self.chain.word_height(word).ok_or_else(unused)?;
+        let hash = self
+            .chain
+            .canonical_hash(height)
+            .ok_or(Error::UnknownParentBlock)?;
+        let bytes = self
+            .store
+            .get(hash.as_bytes())
+            .ok_or(Error::UnknownParentBlock)?;
+        let block = deserialize::<SignedBlock>(&bytes)?.message;
+
+        let proof = block.prove_word(word).ok_or_else(unused)?;
+        if !block.header.verify_word(word, &proof) {
+            return Err(Error::InvalidBodyRoot);
+        }
+
+        Ok((hash, height, proof))
+    }
+
+    fn import_vote(&mut self, vote: &CheckpointVote) -> Result<(), Error> {
+        for event in self.chain.add_vote(vote)? {
+            self.handle_chain_event(event);
+        }
+
+        Ok(())
+    }
+
+    /// Counts the votes that arrived ahead of a block now imported. Gossiped
+    /// votes were ignored on arrival, they are forwarded or rejected now.
+    fn import_orphan_votes(&mut self, hash: BlockHash) {
+        for (vote, source, message_id) in self.orphan_votes.take(&hash) {
+            let result = self.import_vote(&vote);
+            match &result {
+                Ok(()) => info!(
+                    "Counted held vote of {} for {:?}, {} votes left",
+                    vote.validator,
+                    vote.hash,
+                    self.orphan_votes.len()
+                ),
+                Err(e) => debug!("Ignoring held vote for {:?}: {:?}", vote.hash, e),
+            }
+
+            match (message_id, result) {
+                (Some(id), Ok(())) => self.send_to_service(ServiceMessage::Forward(
+                    id,
+                    source,
+                    GossipsubMessage::Vote(vote),
+                )),
+                (Some(id), result) => self.send_to_service(ServiceMessage::ValidationResult(
+                    id,
+                    source,
+                    Self::acceptance(result),
+                )),
+                (None, _) => {}
+            }
+        }
+    }
+
+    /// Asks the service to vote for the canonical checkpoints among
+    /// `enacted`, if the local key is one of the validators.
+    fn vote(&mut self, enacted: &[BlockHash]) {
+        for hash in enacted {
+            let height = match self.chain.height(hash) {
+                Ok(height) => height,
+                Err(_) => continue,
+            };
+
+            // The service skips heights the key voted at already
+            if self.chain.is_checkpoint(height) && height > self.chain.finalized_height() {
+                self.send_to_service(ServiceMessage::NewVote(*hash, height));
+            }
+        }
+    }
+
+    fn hold_future_block(
+        &mut self,
+        signed_block: &SignedBlock,
+        source: &PeerId,
+        message_id: Option<MessageId>,
+        ahead: i64,
+    ) {
+        let wait = ahead - self.max_future_drift as i64;
+        if wait > MAX_FUTURE_BLOCK_HOLD || self.future_blocks.len() >= MAX_FUTURE_BLOCKS {
+            return;
+        }
+
+        debug!(
+            "Holding block {:?}, {}s ahead of the local clock",
+            signed_block.message.hash, ahead
+        );
+
+        self.future_blocks.insert(
+            signed_block.message.hash,
+            (signed_block.clone(), source.clone(), message_id),
+        );
+    }
+
+    /// Imports the held blocks that are no longer too far ahead of the local
+    /// clock.
+    fn import_future_blocks(&mut self) {
+        let latest = Utc::now() + Duration::seconds(self.max_future_drift as i64);
+        let due: Vec<BlockHash> = self
+            .future_blocks
+            .iter()
+            .filter(|(_, (signed_block, _, _))| signed_block.message.header.timestamp <= latest)
+            .map(|(hash, _)| *hash)
+            .collect();
+
+        for hash in due {
+            let (signed_block, source, message_id) = self.future_blocks.remove(&hash).unwrap();
+            let result = self.import_peer_block(&signed_block, &source, message_id.clone());
+            match &result {
+                Ok(()) => info!("Inserted held block {:?} from {}", hash, source),
+                // Held again, waiting on its parent or the clock
+                Err(Error::UnknownParentBlock) | Err(Error::FutureBlock(_)) => continue,
+                Err(e) => warn!("Ignoring invalid held block: {:?}", e),
+            }
+
+            self.finish_validation(signed_block, source, message_id, result);
+        }
+    }
+
+    /// Imports the orphans waiting on a block and their descendants,
+    /// returning how many were imported.
+    fn import_orphans(&mut self, parent_hash: BlockHash) -> usize {
+        let mut imported = 0;
+        let mut parents = vec![parent_hash];
+
+        while let Some(parent_hash) = parents.pop() {
+            for (signed_block, source, message_id) in self.orphans.take(&parent_hash) {
+                let result = self.import_block(&signed_block);
+                match &result {
+                    Ok(()) => {
+                        info!(
+                            "Inserted orphan block {:?} from {}, {} orphans left",
+                            signed_block.message.hash,
+                            source,
+                            self.orphans.len()
+                        );
+
+                        parents.push(signed_block.message.hash);
+                        imported += 1;
+                    }
+                    Err(e) => warn!("Ignoring invalid orphan block: {:?}", e),
+                }
+
+                self.finish_validation(signed_block, source, message_id, result);
+            }
+        }
+
+        imported
+    }
+
+    /// Gossiped orphans and future blocks were ignored on arrival, now that
+    /// they are validated they are forwarded or rejected. Blocks that came
+    /// through sync only count against their peer's score.
+    fn finish_validation(
+        &self,
+        signed_block: SignedBlock,
+        source: PeerId,
+        message_id: Option<MessageId>,
+        result: Result<(), Error>,
+    ) {
+        match (message_id, result) {
+            (Some(id), Ok(())) => self.send_to_service(ServiceMessage::Forward(
+                id,
+                source,
+                GossipsubMessage::Block(signed_block),
+            )),
+            (Some(id), result) => self.send_to_service(ServiceMessage::ValidationResult(
+                id,
+                source,
+                Self::acceptance(result),
+            )),
+            (None, Err(e)) => self.report_import_error(&source, e),
+            (None, Ok(())) => {}
+        }
+    }
+
+    fn handle_chain_event(&mut self, event: ChainEvent) {
+        match event {
+            ChainEvent::Extended { head } => {
+                info!("New chain head {:?}", head);
+
+                self.vote(&[head]);
+            }
+            ChainEvent::Reorg {
+                old_head,
+                new_head,
+                common_ancestor,
+                depth,
+                retracted,
+                enacted,
+            } => {
+                info!(
+                    "Chain reorganized from {:?} to {:?} at common ancestor {:?}, depth {}",
+                    old_head, new_head, common_ancestor, depth
+                );
+                debug!("Retracted {:?}, enacted {:?}", retracted, enacted);
+
+                Display::notice_reorg(depth).unwrap();
+
+                self.vote(&enacted);
+            }
+            ChainEvent::Finalized { hash, height } => {
+                info!("Finalized block {:?} at height {}", hash, height)
+            }
+        }
+
+        let finalized_height = self.chain.finalized_height();
+
+        let blocks = self
+            .chain
+            .recent(RECENT_BLOCKS_TO_DISPLAY)
+            .iter()
+            .filter_map(|hash| self.store.get(hash.as_bytes()))
+            .filter_map(|bytes| deserialize::<SignedBlock>(&bytes).ok())
+            .map(|signed_block| {
+                let finalized = signed_block.message.header.height <= finalized_height;
+
+                (signed_block.display(), finalized)
+            })
+            .collect();
+        let from_genesis = self.chain.head_height() <= RECENT_BLOCKS_TO_DISPLAY as u64;
+
+        self.send_to_service(ServiceMessage::HeadChanged(
+            self.chain.head(),
+            self.chain.head_height(),
+            self.chain.head_weight(),
+        ));
+
+        Display::draw_chain(blocks, from_genesis).unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_acceptance() {
+        let acceptance = |result| match Handler::acceptance(result) {
+            MessageAcceptance::Accept => "accept",
+            MessageAcceptance::Reject(_) => "reject",
+            MessageAcceptance::Ignore => "ignore",
+        };
+
+        assert_eq!(acceptance(Ok(())), "accept");
+        assert_eq!(acceptance(Err(Error::DuplicateBlock)), "ignore");
+        assert_eq!(acceptance(Err(Error::UnknownParentBlock)), "ignore");
+        assert_eq!(acceptance(Err(Error::FutureBlock(60))), "ignore");
+        assert_eq!(acceptance(Err(Error::TimestampBeforeParent)), "reject");
+        assert_eq!(acceptance(Err(Error::DuplicateVote)), "ignore");
+        assert_eq!(acceptance(Err(Error::FinalityConflict)), "ignore");
+        assert_eq!(acceptance(Err(Error::InvalidVote("".into()))), "reject");
+        assert_eq!(acceptance(Err(Error::DBError("closed".into()))), "ignore");
+        assert_eq!(acceptance(Err(Error::InvalidSignature)), "reject");
+        assert_eq!(acceptance(Err(Error::InvalidWordset)), "reject");
+    }
 }
@@ -1,32 +1,55 @@
+use std::collections::HashMap;
 use std::path::Path;
 use std::sync::Arc;
 use std::task::{Context, Poll};
+use std::time::{Duration, Instant};
 
 use async_std::io;
 use futures::{future, prelude::*};
 use libp2p::{
     gossipsub::{MessageId, Topic},
     identity::Keypair,
+    multiaddr::Protocol,
     swarm::NetworkBehaviourAction::GenerateEvent,
     Multiaddr, PeerId, Swarm,
 };
 use tokio::{
     runtime::Handle,
     sync::mpsc::{self, UnboundedReceiver, UnboundedSender},
+    time,
 };
 use void::Void;
 
+use crate::authority::{AuthorityChange, AuthorityKey};
 use crate::behaviour::{
-    types::{BehaviourEvent, GossipsubMessage},
+    types::{
+        blocks_topic, BehaviourEvent, GossipsubMessage, MessageAcceptance, Status, SyncRequest,
+        SyncResponse,
+    },
     Behaviour,
 };
-use crate::block::Block;
+use crate::block::{Block, BlockHash};
 use crate::display::Display;
 use crate::errors::Error;
+use crate::finality::CheckpointVote;
+use crate::keystore::VoteRecord;
+use crate::spec::ChainSpec;
 use crate::store::DiscStore;
 
 mod handler;
+mod orphans;
+mod peers;
+mod scores;
 use handler::{Handler, HandlerMessage};
+use peers::PeerManager;
+use scores::{Misbehaviour, PeerScores};
+
+/// Seconds a block may be timestamped ahead of the local clock before it is
+/// held back, unless configured otherwise.
+pub const DEFAULT_MAX_FUTURE_DRIFT: u64 = 15;
+
+// Redials, score recovery and retries of held blocks are driven by this tick
+const TICK_INTERVAL: Duration = Duration::from_secs(1);
 
 pub struct Service {
     local_keypair: Keypair,
@@ -35,64 +58,163 @@ pub struct Service {
     store: Arc<DiscStore>,
 
     swarm: Swarm<Behaviour>,
+    topic: Topic,
+    peers: PeerManager,
+    scores: PeerScores,
     handler_send: UnboundedSender<HandlerMessage>,
     service_recv: UnboundedReceiver<ServiceMessage>,
+
+    // Blocks are only produced once the initial sync with the
+    // first compatible peer has finished
+    live: bool,
+    syncing_with: Option<PeerId>,
+    pending_stdin: Vec<String>,
+
+    // Latest status of every connected peer, to pick whom to sync from
+    statuses: HashMap<PeerId, Status>,
+
+    // Heights the local key voted at, if it votes on checkpoints
+    votes: Option<VoteRecord>,
 }
 
 #[derive(Debug)]
 pub enum ServiceMessage {
     NewBlock(Block),
-    PropagateGossip(MessageId, PeerId),
+    NewVote(BlockHash, u64),
+    ValidationResult(MessageId, PeerId, MessageAcceptance),
+    Forward(MessageId, PeerId, GossipsubMessage),
+    HeadChanged(BlockHash, u64, u64),
+    SyncRequest(PeerId, u64, SyncRequest),
+    SyncRespond(PeerId, u64, SyncResponse),
+    SyncComplete(PeerId),
+    SyncFailed(PeerId),
+    ReportPeer(PeerId, Misbehaviour),
 }
 
 impl Service {
-    pub fn new(rt_handle: &Handle, store_path: &Path) -> Result<Self, Error> {
+    pub fn new(
+        rt_handle: &Handle,
+        store_path: &Path,
+        keypair: Keypair,
+        network: &str,
+        spec: ChainSpec,
+        enable_mdns: bool,
+        max_future_drift: u64,
+    ) -> Result<Self, Error> {
         let disc_store = DiscStore::open(&store_path)?;
         let store = Arc::new(disc_store);
 
-        let keypair = Keypair::generate_ed25519();
         let peer_id = PeerId::from(keypair.public());
         let transport = libp2p::build_development_transport(keypair.clone())?;
-        let behaviour = Behaviour::new(&peer_id);
+        let behaviour = Behaviour::new(keypair.public(), network, spec.digest(), enable_mdns);
         let swarm = Swarm::new(transport, behaviour, peer_id);
 
+        let validator = match (&keypair, &spec.finality) {
+            (Keypair::Ed25519(kp), Some(finality)) => finality
+                .validators
+                .contains(&AuthorityKey::from(&kp.public())),
+            _ => false,
+        };
+        let votes = match validator {
+            true => Some(VoteRecord::load(store_path)?),
+            false => None,
+        };
+
         let (service_send, service_recv) = mpsc::unbounded_channel::<ServiceMessage>();
-        let handler_send = Handler::new(&rt_handle, &store, service_send.clone());
+        let handler_send = Handler::new(
+            &rt_handle,
+            &store,
+            spec,
+            max_future_drift,
+            service_send.clone(),
+        );
+
+        let peers = PeerManager::new(&store)?;
 
         Ok(Service {
             local_keypair: keypair,
             store: store,
             swarm: swarm,
+            topic: blocks_topic(network),
+            peers: peers,
+            scores: PeerScores::new(),
             handler_send: handler_send,
             service_recv: service_recv,
+            live: false,
+            syncing_with: None,
+            pending_stdin: Vec::new(),
+            statuses: HashMap::new(),
+            votes: votes,
         })
     }
 
     pub fn start(
         &mut self,
         rt_handle: &Handle,
-        to_dial: Option<Multiaddr>,
+        to_dial: Vec<Multiaddr>,
+        bootnodes: Vec<Multiaddr>,
     ) -> Result<(), Box<dyn std::error::Error>> {
-        let topic = Topic::new("monkey-chain".into());
-        self.swarm.subscribe(&topic);
+        self.swarm.subscribe(&self.topic);
+
+        info!("Local peer id {}", Swarm::local_peer_id(&self.swarm));
+
+        for mut addr in bootnodes.iter().cloned() {
+            let peer_id = match addr.pop() {
+                Some(Protocol::P2p(multihash)) => match PeerId::from_multihash(multihash) {
+                    Ok(peer_id) => peer_id,
+                    Err(_) => {
+                        warn!("Ignoring bootnode with invalid peer id: {}", addr);
+                        continue;
+                    }
+                },
+                _ => {
+                    warn!("Ignoring bootnode without a /p2p peer id: {}", addr);
+                    continue;
+                }
+            };
+
+            self.swarm.add_bootnode(&peer_id, addr);
+            if let Err(e) = Swarm::dial(&mut self.swarm, &peer_id) {
+                warn!("Failed to dial bootnode {}: {:?}", peer_id, e);
+            }
+        }
+        if !bootnodes.is_empty() {
+            self.swarm.bootstrap();
+        }
 
-        if let Some(addr) = to_dial {
-            Swarm::dial_addr(&mut self.swarm, addr.clone())?;
-            debug!("Dialed {:?}", addr);
+        for addr in to_dial {
+            self.peers.add_target(addr);
         }
 
+        // Without anyone to sync from, start producing blocks right away
+        if !self.peers.has_targets() && bootnodes.is_empty() {
+            self.live = true;
+        }
+
+        let mut tick = rt_handle.enter(|| time::interval(TICK_INTERVAL));
+
         Swarm::listen_on(&mut self.swarm, "/ip4/0.0.0.0/tcp/0".parse().unwrap()).unwrap();
 
         let mut stdin = io::BufReader::new(io::stdin()).lines();
 
         let mut listening = false;
         rt_handle.block_on(future::poll_fn(move |cx: &mut Context| {
+            while let Poll::Ready(now) = tick.poll_tick(cx) {
+                self.redial(now.into_std());
+                self.recover_scores(now.into_std());
+                self.handler_send.send(HandlerMessage::Tick)?;
+            }
+
             loop {
                 match stdin.try_poll_next_unpin(cx)? {
-                    Poll::Ready(Some(line)) => {
-                        self.handler_send
-                            .send(HandlerMessage::Stdin(line, self.local_keypair.public()))?;
-                    }
+                    Poll::Ready(Some(line)) if line.starts_with('/') => self.handle_command(&line),
+                    Poll::Ready(Some(line)) => match self.live {
+                        true => {
+                            self.handler_send
+                                .send(HandlerMessage::Stdin(line, self.local_keypair.public()))?;
+                        }
+                        false => self.pending_stdin.push(line),
+                    },
                     Poll::Ready(None) => panic!("Stdin closed"),
                     Poll::Pending => break,
                 }
@@ -108,7 +230,7 @@ impl Service {
                                 info!("Listening on {:?}", addr);
                                 listening = true;
                             }
-                            Display::init().unwrap();
+                            self.handler_send.send(HandlerMessage::Listening)?;
                         }
                         break;
                     }
@@ -119,6 +241,9 @@ impl Service {
                 match self.swarm.poll::<Void>() {
                     Poll::Pending => break,
                     Poll::Ready(GenerateEvent(event)) => match event {
+                        BehaviourEvent::InvalidMessage(source) => {
+                            self.report_peer(source, Misbehaviour::UndecodableMessage);
+                        }
                         BehaviourEvent::PeerSubscribed(peer_id, topic_hash) => {
                             debug!("Peer {} subscribed to {}", peer_id, topic_hash);
                         }
@@ -134,6 +259,91 @@ impl Service {
                             self.handler_send
                                 .send(HandlerMessage::Publish(id, source, message))?;
                         }
+                        BehaviourEvent::PeerConnected(peer_id) => {
+                            debug!("Connected to {}", peer_id);
+                        }
+                        BehaviourEvent::PeerDiscovered(peer_id) => {
+                            let local = Swarm::local_peer_id(&self.swarm) == &peer_id;
+                            let connected =
+                                Swarm::connection_info(&mut self.swarm, &peer_id).is_some();
+
+                            if !local && !connected {
+                                debug!("Dialing discovered peer {}", peer_id);
+                                if let Err(e) = Swarm::dial(&mut self.swarm, &peer_id) {
+                                    warn!("Failed to dial {}: {:?}", peer_id, e);
+                                }
+                            }
+                        }
+                        BehaviourEvent::PeerStatus(peer_id, status) => {
+                            debug!("Status from {}: {:?}", peer_id, status);
+
+                            self.statuses.insert(peer_id.clone(), status);
+                            self.handler_send
+                                .send(HandlerMessage::PeerConnected(peer_id))?;
+
+                            if self.syncing_with.is_none() {
+                                self.start_sync()?;
+                            }
+                        }
+                        BehaviourEvent::IncompatiblePeer(peer_id, incompatibility) => {
+                            warn!(
+                                "Disconnecting incompatible peer {}: {:?}",
+                                peer_id, incompatibility
+                            );
+
+                            self.report_peer(peer_id, Misbehaviour::Incompatible);
+
+                            if !self.live && self.syncing_with.is_none() {
+                                self.go_live()?;
+                            }
+                        }
+                        BehaviourEvent::PeerDialed(peer_id, addr) => {
+                            debug!("Dialed {} at {}", peer_id, addr);
+
+                            if let Err(e) = self.peers.connected(peer_id, addr) {
+                                error!("Failed to update address book: {:?}", e);
+                            }
+                        }
+                        BehaviourEvent::PeerDisconnected(peer_id) => {
+                            debug!("Disconnected from {}", peer_id);
+
+                            self.peers.disconnected(&peer_id, Instant::now());
+                            self.statuses.remove(&peer_id);
+
+                            if self.syncing_with.as_ref() == Some(&peer_id) {
+                                warn!("Sync peer {} disconnected", peer_id);
+
+                                self.handler_send.send(HandlerMessage::SyncStop(peer_id))?;
+                                self.start_sync()?;
+                            }
+                        }
+                        BehaviourEvent::DialFailure(addr) => {
+                            self.peers.dial_failed(&addr, Instant::now());
+
+                            let awaiting_peer = self.peers.has_active_targets();
+                            if !self.live && self.syncing_with.is_none() && !awaiting_peer {
+                                warn!("Could not reach any peer to sync with");
+                                self.go_live()?;
+                            }
+                        }
+                        BehaviourEvent::SyncRequest {
+                            peer_id,
+                            id,
+                            request,
+                        } => {
+                            debug!("Sync request {} from {}: {:?}", id, peer_id, request);
+                            self.handler_send
+                                .send(HandlerMessage::SyncRequest(peer_id, id, request))?;
+                        }
+                        BehaviourEvent::SyncResponse {
+                            peer_id,
+                            id,
+                            response,
+                        } => {
+                            debug!("Sync response {} from {}", id, peer_id);
+                            self.handler_send
+                                .send(HandlerMessage::SyncResponse(peer_id, id, response))?;
+                        }
                     },
                     Poll::Ready(unhandled_event) => {
                         debug!("Found unhandled event: {:?}", unhandled_event);
@@ -141,15 +351,22 @@ impl Service {
                 }
             }
 
+            let mut handled = false;
             loop {
-                match self.service_recv.try_recv() {
-                    Ok(service_msg) => {
+                match self.service_recv.poll_recv(cx) {
+                    Poll::Ready(Some(service_msg)) => {
                         self.handle_message(service_msg)?;
+                        handled = true;
                     }
-                    Err(..) => break,
+                    Poll::Ready(None) | Poll::Pending => break,
                 }
             }
 
+            // Handled messages may have queued up work for the swarm
+            if handled {
+                cx.waker().wake_by_ref();
+            }
+
             Poll::Pending
         }))
     }
@@ -170,8 +387,7 @@ impl Service {
 
                 match msg.encode() {
                     Ok(encoded_msg) => {
-                        let topic = Topic::new("monkey-chain".into());
-                        self.swarm.publish(&topic, &encoded_msg);
+                        self.swarm.publish(&self.topic, &encoded_msg);
 
                         self.handler_send
                             .send(HandlerMessage::OwnBlock(signed_block))?;
@@ -181,11 +397,201 @@ impl Service {
                     }
                 }
             }
-            ServiceMessage::PropagateGossip(id, source) => {
-                self.swarm.progagate_message(&id, &source);
+            ServiceMessage::NewVote(hash, height) => {
+                let recorded = match self.votes.as_mut() {
+                    Some(votes) => votes.record(height),
+                    None => Ok(false),
+                };
+
+                match recorded {
+                    Ok(true) => {}
+                    Ok(false) => return Ok(()),
+                    Err(e) => {
+                        error!("Failed to record vote at height {}: {:?}", height, e);
+
+                        return Ok(());
+                    }
+                }
+
+                let keypair = match &self.local_keypair {
+                    Keypair::Ed25519(kp) => kp,
+                    _ => panic!("Only Ed25519 scheme is supported"),
+                };
+
+                let vote = CheckpointVote::new(hash, height, keypair);
+                let msg = GossipsubMessage::Vote(vote.clone());
+
+                match msg.encode() {
+                    Ok(encoded_msg) => {
+                        self.swarm.publish(&self.topic, &encoded_msg);
+
+                        self.handler_send.send(HandlerMessage::OwnVote(vote))?;
+                    }
+                    Err(e) => {
+                        error!("Failed to encode Gossipsub message: {:?}", e);
+                    }
+                }
+            }
+            ServiceMessage::ValidationResult(id, source, acceptance) => match acceptance {
+                MessageAcceptance::Accept => {
+                    self.swarm.progagate_message(&id, &source);
+                }
+                MessageAcceptance::Reject(e) => {
+                    debug!("Rejected message {} from {}", id, source);
+
+                    self.swarm.reject_message(id);
+                    self.report_peer(source, Misbehaviour::InvalidBlock(e));
+                }
+                MessageAcceptance::Ignore => debug!("Ignored message {} from {}", id, source),
+            },
+            ServiceMessage::Forward(id, source, msg) => {
+                // Orphans usually outlive the Gossipsub message cache, in
+                // which case they are published anew
+                if !self.swarm.progagate_message(&id, &source) {
+                    match msg.encode() {
+                        Ok(encoded_msg) => self.swarm.publish(&self.topic, &encoded_msg),
+                        Err(e) => error!("Failed to encode Gossipsub message: {:?}", e),
+                    }
+                }
+            }
+            ServiceMessage::HeadChanged(head, height, weight) => {
+                self.swarm.set_head(head, height, weight);
+            }
+            ServiceMessage::SyncRequest(peer_id, id, request) => {
+                self.swarm.send_sync_request(&peer_id, id, request);
+            }
+            ServiceMessage::SyncRespond(peer_id, id, response) => {
+                self.swarm.send_sync_response(&peer_id, id, response);
+            }
+            ServiceMessage::ReportPeer(peer_id, misbehaviour) => {
+                self.report_peer(peer_id, misbehaviour);
+            }
+            ServiceMessage::SyncComplete(peer_id) => {
+                if self.syncing_with.as_ref() == Some(&peer_id) {
+                    self.go_live()?;
+                }
+            }
+            ServiceMessage::SyncFailed(peer_id) => {
+                if self.syncing_with.as_ref() == Some(&peer_id) {
+                    warn!("Sync with {} failed", peer_id);
+
+                    self.statuses.remove(&peer_id);
+                    self.start_sync()?;
+                }
             }
         };
 
         Ok(())
     }
+
+    fn redial(&mut self, now: Instant) {
+        for addr in self.peers.due(now) {
+            debug!("Dialing {}", addr);
+
+            if let Err(e) = Swarm::dial_addr(&mut self.swarm, addr.clone()) {
+                warn!("Failed to dial {}: {:?}", addr, e);
+
+                self.peers.dial_failed(&addr, now);
+            }
+        }
+    }
+
+    fn report_peer(&mut self, peer_id: PeerId, misbehaviour: Misbehaviour) {
+        debug!("Peer {} misbehaved: {:?}", peer_id, misbehaviour);
+
+        if self.scores.report(&peer_id, &misbehaviour, Instant::now()) {
+            warn!("Banning peer {} after {:?}", peer_id, misbehaviour);
+
+            Swarm::ban_peer_id(&mut self.swarm, peer_id);
+        }
+    }
+
+    fn recover_scores(&mut self, now: Instant) {
+        for peer_id in self.scores.recover(now) {
+            info!("Ban of peer {} expired", peer_id);
+
+            Swarm::unban_peer_id(&mut self.swarm, peer_id);
+        }
+    }
+
+    fn handle_command(&mut self, command: &str) {
+        let args: Vec<&str> = command.split_whitespace().collect();
+        match args.as_slice() {
+            ["/peers"] => {
+                let peers = self
+                    .scores
+                    .scores()
+                    .into_iter()
+                    .map(|(peer_id, score, banned)| (peer_id.to_string(), score, banned))
+                    .collect();
+
+                Display::draw_peers(peers).unwrap();
+            }
+            ["/authorities", path] => match AuthorityChange::load(Path::new(path)) {
+                Ok(change) => {
+                    let handler_msg = HandlerMessage::AuthorityChange(change);
+                    if let Err(e) = self.handler_send.send(handler_msg) {
+                        error!("Error sending message between Service and Handler: {:?}", e);
+                    }
+                }
+                Err(e) => warn!("Failed to load authority change from {}: {:?}", path, e),
+            },
+            ["/prove", word] => {
+                let handler_msg = HandlerMessage::ProveWord(word.to_lowercase());
+                if let Err(e) = self.handler_send.send(handler_msg) {
+                    error!("Error sending message between Service and Handler: {:?}", e);
+                }
+            }
+            _ => warn!("Unknown command {}", command),
+        }
+    }
+
+    /// Syncs with the peer announcing the heaviest chain if it weighs more
+    /// than ours, and goes live otherwise.
+    fn start_sync(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let local_weight = self
+            .swarm
+            .local_status()
+            .map_or(0, |local_status| local_status.weight);
+
+        let heaviest = self
+            .statuses
+            .iter()
+            .filter(|(_, status)| status.weight > local_weight)
+            .max_by_key(|(_, status)| status.weight)
+            .map(|(peer_id, status)| (peer_id.clone(), status.head, status.height));
+
+        match heaviest {
+            Some((peer_id, head, height)) => {
+                info!(
+                    "Peer {} has a heavier chain at height {}, syncing",
+                    peer_id, height
+                );
+
+                self.syncing_with = Some(peer_id.clone());
+                self.handler_send
+                    .send(HandlerMessage::SyncStart(peer_id, head, height))?;
+
+                Ok(())
+            }
+            None => self.go_live(),
+        }
+    }
+
+    fn go_live(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        self.syncing_with = None;
+        if self.live {
+            return Ok(());
+        }
+
+        info!("Going live");
+        self.live = true;
+
+        for line in self.pending_stdin.drain(..) {
+            self.handler_send
+                .send(HandlerMessage::Stdin(line, self.local_keypair.public()))?;
+        }
+
+        Ok(())
+    }
 }
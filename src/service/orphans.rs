@@ -0,0 +1,240 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::{gossipsub::MessageId, PeerId};
+
+use crate::block::{BlockHash, SignedBlock};
+use crate::finality::CheckpointVote;
+
+const MAX_ORPHANS: usize = 256;
+const ORPHAN_TTL: Duration = Duration::from_secs(300);
+
+/// A message that has to wait for a block that is not known yet.
+pub trait Orphan {
+    /// Hash of the block waited for.
+    fn awaits(&self) -> BlockHash;
+
+    fn is_same(&self, other: &Self) -> bool;
+}
+
+impl Orphan for SignedBlock {
+    fn awaits(&self) -> BlockHash {
+        self.message.header.parent_hash
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        self.message.hash == other.message.hash
+    }
+}
+
+/// Votes arriving ahead of their block would be lost otherwise.
+impl Orphan for CheckpointVote {
+    fn awaits(&self) -> BlockHash {
+        self.hash
+    }
+
+    fn is_same(&self, other: &Self) -> bool {
+        self == other
+    }
+}
+
+struct Held<T> {
+    orphan: T,
+    source: PeerId,
+    received: Instant,
+
+    // Set for orphans received over Gossipsub, to forward them once imported
+    message_id: Option<MessageId>,
+}
+
+/// Orphans keyed by the hash of the block they wait for.
+pub struct OrphanPool<T> {
+    orphans: HashMap<BlockHash, Vec<Held<T>>>,
+    len: usize,
+}
+
+impl<T: Orphan> OrphanPool<T> {
+    pub fn new() -> Self {
+        OrphanPool {
+            orphans: HashMap::new(),
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn contains(&self, orphan: &T) -> bool {
+        self.orphans
+            .get(&orphan.awaits())
+            .is_some_and(|waiting| waiting.iter().any(|held| held.orphan.is_same(orphan)))
+    }
+
+    /// Returns `true` if this is the first orphan waiting for its block,
+    /// i.e. if the block still needs to be requested. Orphans already held
+    /// are left alone.
+    pub fn insert(&mut self, orphan: T, source: PeerId, message_id: Option<MessageId>) -> bool {
+        self.prune(Instant::now());
+
+        if self.contains(&orphan) {
+            return false;
+        }
+
+        let waiting = self.orphans.entry(orphan.awaits()).or_default();
+        let first = waiting.is_empty();
+
+        waiting.push(Held {
+            orphan: orphan,
+            source: source,
+            received: Instant::now(),
+            message_id: message_id,
+        });
+        self.len += 1;
+
+        if self.len > MAX_ORPHANS {
+            self.evict_oldest();
+        }
+
+        first
+    }
+
+    /// Removes and returns the orphans waiting for the given block.
+    pub fn take(&mut self, hash: &BlockHash) -> Vec<(T, PeerId, Option<MessageId>)> {
+        let waiting = self.orphans.remove(hash).unwrap_or_default();
+        self.len -= waiting.len();
+
+        waiting
+            .into_iter()
+            .map(|held| (held.orphan, held.source, held.message_id))
+            .collect()
+    }
+
+    /// Drops every orphan received more than `ORPHAN_TTL` before `now`.
+    pub fn prune(&mut self, now: Instant) {
+        for waiting in self.orphans.values_mut() {
+            waiting.retain(|held| now.duration_since(held.received) < ORPHAN_TTL);
+        }
+        self.orphans.retain(|_, waiting| !waiting.is_empty());
+
+        self.len = self.orphans.values().map(Vec::len).sum();
+    }
+
+    fn evict_oldest(&mut self) {
+        let oldest = self
+            .orphans
+            .iter()
+            .flat_map(|(hash, waiting)| {
+                waiting
+                    .iter()
+                    .enumerate()
+                    .map(move |(index, held)| (held.received, *hash, index))
+            })
+            .min();
+
+        if let Some((_, hash, index)) = oldest {
+            let waiting = self.orphans.get_mut(&hash).unwrap();
+            waiting.remove(index);
+            if waiting.is_empty() {
+                self.orphans.remove(&hash);
+            }
+
+            self.len -= 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::block::Block;
+    use crate::spec::ChainSpec;
+
+    use libp2p::identity::ed25519::Keypair;
+
+    fn orphan(parent_hash: BlockHash) -> SignedBlock {
+        let wordlist = vec![
+            "and".to_string(),
+            "for".to_string(),
+            "that".to_string(),
+            "this".to_string(),
+        ];
+        let proposer = Keypair::generate();
+
+        Block::new(
+            wordlist,
+            proposer.public(),
+            parent_hash,
+            2,
+            &ChainSpec::default(),
+        )
+        .unwrap()
+        .sign(&proposer)
+    }
+
+    #[test]
+    fn test_take() {
+        let mut pool = OrphanPool::new();
+        let parent_hash = BlockHash::digest(b"parent");
+
+        let first = orphan(parent_hash);
+        assert!(pool.insert(first.clone(), PeerId::random(), None));
+        assert!(!pool.insert(first.clone(), PeerId::random(), None));
+        assert!(!pool.insert(orphan(parent_hash), PeerId::random(), None));
+        assert!(pool.insert(orphan(BlockHash::digest(b"other")), PeerId::random(), None));
+        assert!(pool.contains(&first));
+        assert_eq!(pool.len(), 3);
+
+        assert_eq!(pool.take(&parent_hash).len(), 2);
+        assert!(pool.take(&parent_hash).is_empty());
+        assert!(!pool.contains(&first));
+        assert_eq!(pool.len(), 1);
+    }
+
+    #[test]
+    fn test_evict_by_size() {
+        let mut pool = OrphanPool::new();
+
+        let oldest = orphan(BlockHash::digest(b"oldest"));
+        pool.insert(oldest.clone(), PeerId::random(), None);
+        for i in 0..MAX_ORPHANS {
+            let parent_hash = BlockHash::digest(&i.to_be_bytes());
+            pool.insert(orphan(parent_hash), PeerId::random(), None);
+        }
+
+        assert_eq!(pool.len(), MAX_ORPHANS);
+        assert!(!pool.contains(&oldest));
+    }
+
+    #[test]
+    fn test_orphan_votes() {
+        let mut pool = OrphanPool::new();
+        let keypair = Keypair::generate();
+        let hash = BlockHash::digest(b"checkpoint");
+
+        let vote = CheckpointVote::new(hash, 10, &keypair);
+        assert!(pool.insert(vote.clone(), PeerId::random(), None));
+        assert!(!pool.insert(vote.clone(), PeerId::random(), None));
+        assert!(!pool.insert(
+            CheckpointVote::new(hash, 10, &Keypair::generate()),
+            PeerId::random(),
+            None
+        ));
+        assert_eq!(pool.len(), 2);
+
+        assert_eq!(pool.take(&hash).len(), 2);
+        assert!(!pool.contains(&vote));
+    }
+
+    #[test]
+    fn test_evict_by_age() {
+        let mut pool = OrphanPool::new();
+        pool.insert(orphan(BlockHash::digest(b"parent")), PeerId::random(), None);
+
+        pool.prune(Instant::now());
+        assert_eq!(pool.len(), 1);
+
+        pool.prune(Instant::now() + ORPHAN_TTL);
+        assert_eq!(pool.len(), 0);
+    }
+}
@@ -0,0 +1,221 @@
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use bincode::{deserialize, serialize};
+use libp2p::{Multiaddr, PeerId};
+
+use crate::errors::Error;
+use crate::store::DiscStore;
+
+const ADDRESS_BOOK_KEY: &[u8] = b"peers";
+const MAX_KNOWN_PEERS: usize = 64;
+
+const BASE_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(300);
+
+enum DialState {
+    Dialing { attempts: u32 },
+    Connected(PeerId),
+    Backoff { attempts: u32, until: Instant },
+}
+
+fn backoff(attempts: u32) -> Duration {
+    BASE_BACKOFF
+        .checked_mul(1 << attempts.min(16))
+        .map_or(MAX_BACKOFF, |backoff| backoff.min(MAX_BACKOFF))
+}
+
+/// Keeps track of the addresses we dial, redialing them with exponential
+/// backoff, along with an address book of peers we managed to connect to.
+pub struct PeerManager {
+    store: Arc<DiscStore>,
+
+    // Most recently connected peers first
+    known: Vec<(PeerId, Multiaddr)>,
+    targets: HashMap<Multiaddr, DialState>,
+}
+
+impl PeerManager {
+    pub fn new(store: &Arc<DiscStore>) -> Result<Self, Error> {
+        let known = match store.get(ADDRESS_BOOK_KEY) {
+            Some(bytes) => deserialize::<Vec<(Vec<u8>, Vec<u8>)>>(&bytes)?
+                .into_iter()
+                .filter_map(|(peer_id, addr)| {
+                    let peer_id = PeerId::from_bytes(peer_id).ok()?;
+                    let addr = Multiaddr::try_from(addr).ok()?;
+
+                    Some((peer_id, addr))
+                })
+                .collect(),
+            None => Vec::new(),
+        };
+
+        let mut peers = PeerManager {
+            store: Arc::clone(store),
+            known: Vec::new(),
+            targets: HashMap::new(),
+        };
+
+        for (_, addr) in known.iter() {
+            peers.add_target(addr.clone());
+        }
+        peers.known = known;
+
+        Ok(peers)
+    }
+
+    /// Adds an address to keep a connection to, to be dialed right away.
+    pub fn add_target(&mut self, addr: Multiaddr) {
+        self.targets.entry(addr).or_insert(DialState::Backoff {
+            attempts: 0,
+            until: Instant::now(),
+        });
+    }
+
+    pub fn has_targets(&self) -> bool {
+        !self.targets.is_empty()
+    }
+
+    /// Whether any target is connected or still being dialed.
+    pub fn has_active_targets(&self) -> bool {
+        self.targets.values().any(|state| match state {
+            DialState::Dialing { .. } | DialState::Connected(_) => true,
+            DialState::Backoff { .. } => false,
+        })
+    }
+
+    /// Returns the addresses whose backoff has elapsed, which are expected
+    /// to be dialed right away.
+    pub fn due(&mut self, now: Instant) -> Vec<Multiaddr> {
+        let mut due = Vec::new();
+
+        for (addr, state) in self.targets.iter_mut() {
+            if let DialState::Backoff { attempts, until } = state {
+                if *until <= now {
+                    *state = DialState::Dialing {
+                        attempts: *attempts,
+                    };
+                    due.push(addr.clone());
+                }
+            }
+        }
+
+        due
+    }
+
+    /// Records a successful outbound connection in the address book.
+    pub fn connected(&mut self, peer_id: PeerId, addr: Multiaddr) -> Result<(), Error> {
+        self.targets
+            .insert(addr.clone(), DialState::Connected(peer_id.clone()));
+
+        self.known.retain(|(known_id, _)| known_id != &peer_id);
+        self.known.insert(0, (peer_id, addr));
+        self.known.truncate(MAX_KNOWN_PEERS);
+
+        self.persist()
+    }
+
+    pub fn disconnected(&mut self, peer_id: &PeerId, now: Instant) {
+        for state in self.targets.values_mut() {
+            match state {
+                DialState::Connected(connected_id) if connected_id == peer_id => {
+                    *state = DialState::Backoff {
+                        attempts: 0,
+                        until: now + backoff(0),
+                    };
+                }
+                _ => {}
+            }
+        }
+    }
+
+    pub fn dial_failed(&mut self, addr: &Multiaddr, now: Instant) {
+        if let Some(state) = self.targets.get_mut(addr) {
+            let attempts = match state {
+                DialState::Dialing { attempts } | DialState::Backoff { attempts, .. } => {
+                    *attempts + 1
+                }
+                DialState::Connected(_) => return,
+            };
+
+            debug!("Redialing {} in {:?}", addr, backoff(attempts));
+
+            *state = DialState::Backoff {
+                attempts: attempts,
+                until: now + backoff(attempts),
+            };
+        }
+    }
+
+    fn persist(&self) -> Result<(), Error> {
+        let known: Vec<(Vec<u8>, Vec<u8>)> = self
+            .known
+            .iter()
+            .map(|(peer_id, addr)| (peer_id.as_bytes().to_vec(), addr.to_vec()))
+            .collect();
+
+        self.store.put(ADDRESS_BOOK_KEY, &serialize(&known)?)?;
+        self.store.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::path::Path;
+
+    fn open_store(name: &str) -> Arc<DiscStore> {
+        let path = Path::new(".data").join(".test").join(name);
+        let _ = std::fs::remove_dir_all(&path);
+
+        Arc::new(DiscStore::open(&path).unwrap())
+    }
+
+    #[test]
+    fn test_address_book() {
+        let store = open_store("peers_address_book");
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let peer_id = PeerId::random();
+
+        let mut peers = PeerManager::new(&store).unwrap();
+        assert!(!peers.has_targets());
+
+        peers.connected(peer_id, addr.clone()).unwrap();
+
+        let mut reloaded = PeerManager::new(&store).unwrap();
+        assert!(reloaded.has_targets());
+        assert_eq!(reloaded.due(Instant::now()), vec![addr]);
+    }
+
+    #[test]
+    fn test_backoff() {
+        let store = open_store("peers_backoff");
+        let addr: Multiaddr = "/ip4/127.0.0.1/tcp/4001".parse().unwrap();
+        let peer_id = PeerId::random();
+        let mut peers = PeerManager::new(&store).unwrap();
+        peers.add_target(addr.clone());
+        let now = Instant::now();
+
+        assert_eq!(peers.due(now), vec![addr.clone()]);
+        assert!(peers.due(now).is_empty());
+        assert!(peers.has_active_targets());
+
+        peers.dial_failed(&addr, now);
+        assert!(!peers.has_active_targets());
+        assert_eq!(peers.due(now + backoff(1)), vec![addr.clone()]);
+
+        peers.dial_failed(&addr, now);
+        assert!(peers.due(now + backoff(1)).is_empty());
+        assert_eq!(peers.due(now + backoff(2)), vec![addr.clone()]);
+
+        peers.connected(peer_id.clone(), addr.clone()).unwrap();
+        assert!(peers.due(now + MAX_BACKOFF).is_empty());
+
+        peers.disconnected(&peer_id, now);
+        assert_eq!(peers.due(now + BASE_BACKOFF), vec![addr]);
+        assert_eq!(backoff(64), MAX_BACKOFF);
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use libp2p::PeerId;
+
+use crate::errors::Error;
+
+const BAN_THRESHOLD: i32 = -100;
+const BAN_DURATION: Duration = Duration::from_secs(600);
+
+// Points a negative score recovers on every call to `recover`
+const SCORE_RECOVERY: i32 = 1;
+
+#[derive(Debug)]
+pub enum Misbehaviour {
+    InvalidBlock(Error),
+    UndecodableMessage,
+    SyncTimeout,
+    Incompatible,
+}
+
+impl Misbehaviour {
+    fn penalty(&self) -> i32 {
+        match self {
+            // Other network or protocol version, banned until it might have
+            // switched over
+            Misbehaviour::Incompatible => BAN_THRESHOLD,
+
+            // Cannot happen by accident with an honest implementation
+            Misbehaviour::InvalidBlock(Error::InvalidSignature)
+            | Misbehaviour::InvalidBlock(Error::InvalidBlockHash)
+            | Misbehaviour::InvalidBlock(Error::InvalidBodyRoot)
+            | Misbehaviour::InvalidBlock(Error::DuplicateWord(_)) => -50,
+
+            // Unknown parents and duplicates are expected while gossip races
+            // with sync, finality conflicts and future blocks can come from
+            // honest peers, the rest are local failures. These are the
+            // errors gossip validation ignores.
+            Misbehaviour::InvalidBlock(Error::DuplicateBlock)
+            | Misbehaviour::InvalidBlock(Error::UnknownParentBlock)
+            | Misbehaviour::InvalidBlock(Error::DuplicateVote)
+            | Misbehaviour::InvalidBlock(Error::FinalityConflict)
+            | Misbehaviour::InvalidBlock(Error::FutureBlock(_))
+            | Misbehaviour::InvalidBlock(Error::StdError(_))
+            | Misbehaviour::InvalidBlock(Error::DBError(_))
+            | Misbehaviour::InvalidBlock(Error::SerdeError(_)) => 0,
+
+            // Everything else is rejected by gossip validation
+            Misbehaviour::InvalidBlock(_) => -20,
+            Misbehaviour::UndecodableMessage => -20,
+
+            // Possibly just a slow link, but it held up our sync
+            Misbehaviour::SyncTimeout => -10,
+        }
+    }
+}
+
+/// Per-peer reputation, lowered by misbehaviour and slowly recovering over
+/// time. Peers dropping below `BAN_THRESHOLD` are banned for a while.
+pub struct PeerScores {
+    scores: HashMap<PeerId, i32>,
+    bans: HashMap<PeerId, Instant>,
+}
+
+impl PeerScores {
+    pub fn new() -> Self {
+        PeerScores {
+            scores: HashMap::new(),
+            bans: HashMap::new(),
+        }
+    }
+
+    /// Returns `true` if the peer got banned because of this report.
+    pub fn report(&mut self, peer_id: &PeerId, misbehaviour: &Misbehaviour, now: Instant) -> bool {
+        let penalty = misbehaviour.penalty();
+        if penalty == 0 || self.bans.contains_key(peer_id) {
+            return false;
+        }
+
+        let score = self.scores.entry(peer_id.clone()).or_insert(0);
+        *score += penalty;
+
+        if *score <= BAN_THRESHOLD {
+            self.bans.insert(peer_id.clone(), now + BAN_DURATION);
+            return true;
+        }
+
+        false
+    }
+
+    /// Lets negative scores recover, and returns the peers whose ban has
+    /// expired. These start over with a clean score.
+    pub fn recover(&mut self, now: Instant) -> Vec<PeerId> {
+        self.scores.retain(|_, score| {
+            *score = (*score + SCORE_RECOVERY).min(0);
+            *score < 0
+        });
+
+        let expired: Vec<PeerId> = self
+            .bans
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(peer_id, _)| peer_id.clone())
+            .collect();
+
+        for peer_id in expired.iter() {
+            self.bans.remove(peer_id);
+            self.scores.remove(peer_id);
+        }
+
+        expired
+    }
+
+    /// Scores of all peers with a penalty or a ban, lowest first.
+    pub fn scores(&self) -> Vec<(PeerId, i32, bool)> {
+        let mut scores: Vec<(PeerId, i32, bool)> = self
+            .scores
+            .iter()
+            .map(|(peer_id, score)| (peer_id.clone(), *score, self.bans.contains_key(peer_id)))
+            .collect();
+        scores.sort_by_key(|(_, score, _)| *score);
+
+        scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ban() {
+        let mut scores = PeerScores::new();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        let duplicate = Misbehaviour::InvalidBlock(Error::DuplicateBlock);
+        assert!(!scores.report(&peer_id, &duplicate, now));
+        assert!(scores.scores().is_empty());
+
+        let invalid = Misbehaviour::InvalidBlock(Error::InvalidSignature);
+        assert!(!scores.report(&peer_id, &invalid, now));
+        assert_eq!(scores.scores(), vec![(peer_id.clone(), -50, false)]);
+
+        assert!(scores.report(&peer_id, &invalid, now));
+        assert!(!scores.report(&peer_id, &invalid, now));
+        assert_eq!(scores.scores(), vec![(peer_id.clone(), -100, true)]);
+
+        assert!(scores.recover(now).is_empty());
+        assert_eq!(scores.recover(now + BAN_DURATION), vec![peer_id]);
+        assert!(scores.scores().is_empty());
+    }
+
+    #[test]
+    fn test_recover() {
+        let mut scores = PeerScores::new();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        scores.report(&peer_id, &Misbehaviour::UndecodableMessage, now);
+        for _ in 0..19 {
+            scores.recover(now);
+        }
+        assert_eq!(scores.scores(), vec![(peer_id, -1, false)]);
+
+        scores.recover(now);
+        assert!(scores.scores().is_empty());
+    }
+
+    #[test]
+    fn test_penalty() {
+        let ignored = Misbehaviour::InvalidBlock(Error::FutureBlock(60));
+        assert_eq!(ignored.penalty(), 0);
+
+        let unused = Misbehaviour::InvalidBlock(Error::UnusedWord("ant".to_string()));
+        assert_eq!(unused.penalty(), -20);
+    }
+
+    #[test]
+    fn test_incompatible_ban_expires() {
+        let mut scores = PeerScores::new();
+        let peer_id = PeerId::random();
+        let now = Instant::now();
+
+        assert!(scores.report(&peer_id, &Misbehaviour::Incompatible, now));
+        assert!(scores.recover(now).is_empty());
+        assert_eq!(scores.recover(now + BAN_DURATION), vec![peer_id]);
+    }
+}
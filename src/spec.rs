@@ -0,0 +1,377 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use serde::{Deserialize, Serialize};
+
+use crate::authority::AuthorityKey;
+use crate::block::{BlockHash, WordRule};
+use crate::dictionary::{self, DICTIONARY};
+use crate::errors::Error;
+
+/// Highest difficulty a chain can start at or retarget to. Far out of reach
+/// of mining on a CPU already, higher values would only stall the chain.
+pub const MAX_DIFFICULTY: u32 = 64;
+
+/// Rules taking over from a given height on.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Epoch {
+    /// First height the epoch applies to.
+    pub height: u64,
+    pub rules: Vec<WordRule>,
+
+    /// Dictionary file, relative to the chain spec. Epochs without one keep
+    /// the dictionary of the previous epoch.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub dictionary: Option<PathBuf>,
+
+    #[serde(skip)]
+    words: Option<Arc<BTreeSet<String>>>,
+}
+
+/// Proof-of-work parameters. Blocks only need to be mined if a chain spec
+/// has them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ProofOfWork {
+    /// Leading zero bits required of the block hash up to the first
+    /// adjustment.
+    pub difficulty: u32,
+
+    /// Number of blocks between difficulty adjustments.
+    pub retarget_interval: u64,
+
+    /// Seconds a block should take to mine on average.
+    pub block_time: u64,
+}
+
+/// Proof-of-authority parameters. Only the listed proposers may produce
+/// blocks if a chain spec has them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ProofOfAuthority {
+    /// Hex encoded public keys of the initial authorities.
+    pub authorities: Vec<AuthorityKey>,
+
+    /// Whether the authorities take turns, the block at height `h` being
+    /// proposed by authority number `h % authorities`.
+    #[serde(default)]
+    pub round_robin: bool,
+}
+
+/// Finality parameters. Checkpoints voted for by two thirds of the
+/// validators become final if a chain spec has them.
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct Finality {
+    /// Hex encoded public keys of the peers voting on checkpoints.
+    pub validators: Vec<AuthorityKey>,
+
+    /// Blocks at multiples of this height are voted on.
+    pub checkpoint_interval: u64,
+}
+
+/// Protocol parameters all peers of a chain have to agree on, loaded from
+/// a JSON file such as
+///
+/// ```json
+/// {
+///   "name": "monkey",
+///   "rules": [{ "rule": "length", "words": 4 }, { "rule": "dictionary" }],
+///   "epochs": [
+///     {
+///       "height": 100,
+///       "rules": [{ "rule": "length", "words": 5 }, { "rule": "dictionary" }],
+///       "dictionary": "animals.txt"
+///     }
+///   ]
+/// }
+/// ```
+#[derive(Clone, Debug, PartialEq, Deserialize, Serialize)]
+pub struct ChainSpec {
+    pub name: String,
+
+    /// Checked in order against every block body, up to the first epoch.
+    pub rules: Vec<WordRule>,
+
+    /// Ordered by height.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub epochs: Vec<Epoch>,
+
+    /// Number of blocks after which a used word may be used again. Words
+    /// can only be used once per chain if unset.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub word_cooldown: Option<u64>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_of_work: Option<ProofOfWork>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub proof_of_authority: Option<ProofOfAuthority>,
+
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub finality: Option<Finality>,
+
+    /// Seconds a block has to be timestamped after its parent.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub min_block_interval: Option<u64>,
+
+    /// Number of blocks in a row a proposer may produce.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_consecutive_blocks: Option<u64>,
+}
+
+impl Default for ChainSpec {
+    fn default() -> Self {
+        ChainSpec {
+            name: "monkey".to_string(),
+            rules: vec![WordRule::Length { words: 4 }, WordRule::Dictionary],
+            epochs: Vec::new(),
+            word_cooldown: None,
+            proof_of_work: None,
+            proof_of_authority: None,
+            finality: None,
+            min_block_interval: None,
+            max_consecutive_blocks: None,
+        }
+    }
+}
+
+fn has_duplicates(keys: &[AuthorityKey]) -> bool {
+    let distinct: BTreeSet<_> = keys.iter().collect();
+
+    distinct.len() != keys.len()
+}
+
+impl ChainSpec {
+    pub fn load(path: &Path) -> Result<Self, Error> {
+        let json = fs::read_to_string(path)?;
+
+        let mut spec: ChainSpec =
+            serde_json::from_str(&json).map_err(|e| Error::InvalidChainSpec(e.to_string()))?;
+
+        if spec.word_cooldown == Some(0) {
+            return Err(Error::InvalidChainSpec("word cooldown of 0 blocks".into()));
+        }
+
+        if spec.max_consecutive_blocks == Some(0) {
+            return Err(Error::InvalidChainSpec(
+                "maximum of 0 consecutive blocks".into(),
+            ));
+        }
+
+        if let Some(poa) = &spec.proof_of_authority {
+            if poa.authorities.is_empty() {
+                return Err(Error::InvalidChainSpec("no authorities".into()));
+            }
+
+            // A repeated key would get extra turns and count twice towards
+            // majorities
+            if has_duplicates(&poa.authorities) {
+                return Err(Error::InvalidChainSpec("repeated authorities".into()));
+            }
+        }
+
+        if let Some(finality) = &spec.finality {
+            if finality.validators.is_empty() || finality.checkpoint_interval == 0 {
+                return Err(Error::InvalidChainSpec(
+                    "finality needs validators and a checkpoint interval".into(),
+                ));
+            }
+
+            // A repeated key would inflate the two thirds needed to finalize
+            if has_duplicates(&finality.validators) {
+                return Err(Error::InvalidChainSpec("repeated validators".into()));
+            }
+        }
+
+        if let Some(pow) = &spec.proof_of_work {
+            if pow.retarget_interval < 2 || pow.block_time == 0 {
+                return Err(Error::InvalidChainSpec(
+                    "proof of work needs a retarget interval of at least 2 blocks \
+                     and a block time"
+                        .into(),
+                ));
+            }
+
+            if pow.difficulty > MAX_DIFFICULTY {
+                return Err(Error::InvalidChainSpec(format!(
+                    "difficulty {} is above the maximum of {}",
+                    pow.difficulty, MAX_DIFFICULTY
+                )));
+            }
+        }
+
+        let mut previous = 0;
+        for epoch in spec.epochs.iter_mut() {
+            if epoch.height <= previous {
+                return Err(Error::InvalidChainSpec(format!(
+                    "epoch at height {} is out of order",
+                    epoch.height
+                )));
+            }
+            previous = epoch.height;
+
+            if let Some(dictionary) = &epoch.dictionary {
+                let dictionary_path = path.parent().unwrap_or(Path::new(".")).join(dictionary);
+                epoch.words = Some(Arc::new(dictionary::load(&dictionary_path)?));
+            }
+        }
+
+        Ok(spec)
+    }
+
+    /// Epochs started at or before `height`.
+    fn epochs_at(&self, height: u64) -> impl Iterator<Item = &Epoch> {
+        self.epochs
+            .iter()
+            .take_while(move |epoch| epoch.height <= height)
+    }
+
+    /// Rules a block at `height` has to satisfy.
+    pub fn rules_at(&self, height: u64) -> &[WordRule] {
+        match self.epochs_at(height).last() {
+            Some(epoch) => &epoch.rules,
+            None => &self.rules,
+        }
+    }
+
+    /// Dictionary the words of a block at `height` are checked against.
+    pub fn dictionary_at(&self, height: u64) -> &BTreeSet<String> {
+        self.epochs_at(height)
+            .filter_map(|epoch| epoch.words.as_deref())
+            .last()
+            .unwrap_or(&DICTIONARY)
+    }
+
+    /// Digest over the spec and the dictionaries it refers to, used to tell
+    /// whether two peers run the same protocol rules.
+    pub fn digest(&self) -> BlockHash {
+        let mut encoded = serde_json::to_vec(self).unwrap();
+        for words in self.epochs.iter().filter_map(|epoch| epoch.words.as_ref()) {
+            encoded.extend_from_slice(dictionary::digest(words).as_bytes());
+        }
+
+        BlockHash::digest(&encoded)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use libp2p::identity::ed25519::Keypair;
+
+    fn write_spec(name: &str, json: &str) -> PathBuf {
+        let dir = Path::new(".data").join(".test").join(name);
+        fs::create_dir_all(&dir).unwrap();
+
+        let path = dir.join("spec.json");
+        fs::write(&path, json).unwrap();
+
+        path
+    }
+
+    #[test]
+    fn test_load() {
+        let json = r#"{
+            "name": "short words",
+            "rules": [
+                { "rule": "min_length", "words": 2 },
+                { "rule": "letters_per_word", "min": 2, "max": 4 },
+                { "rule": "starts_with", "letter": "t" }
+            ]
+        }"#;
+        let path = write_spec("spec_load", json);
+
+        let spec = ChainSpec::load(&path).unwrap();
+        assert_eq!(spec.rules[2], WordRule::StartsWith { letter: 't' });
+        assert_ne!(spec.digest(), ChainSpec::default().digest());
+
+        let json = r#"{ "name": "x", "rules": [{ "rule": "rhymes" }] }"#;
+        let path = write_spec("spec_load", json);
+        assert!(matches!(
+            ChainSpec::load(&path),
+            Err(Error::InvalidChainSpec(_))
+        ));
+
+        let json = r#"{
+            "name": "unminable",
+            "rules": [],
+            "proof_of_work": { "difficulty": 65, "retarget_interval": 10, "block_time": 30 }
+        }"#;
+        let path = write_spec("spec_load", json);
+        assert!(matches!(
+            ChainSpec::load(&path),
+            Err(Error::InvalidChainSpec(_))
+        ));
+
+        let key = AuthorityKey::from(&Keypair::generate().public());
+        let json = format!(
+            r#"{{
+                "name": "repeated",
+                "rules": [],
+                "proof_of_authority": {{ "authorities": ["{}", "{}"] }}
+            }}"#,
+            key, key
+        );
+        let path = write_spec("spec_load", &json);
+        assert!(matches!(
+            ChainSpec::load(&path),
+            Err(Error::InvalidChainSpec(_))
+        ));
+
+        let json = format!(
+            r#"{{
+                "name": "repeated",
+                "rules": [],
+                "finality": {{ "validators": ["{}", "{}"], "checkpoint_interval": 10 }}
+            }}"#,
+            key, key
+        );
+        let path = write_spec("spec_load", &json);
+        assert!(matches!(
+            ChainSpec::load(&path),
+            Err(Error::InvalidChainSpec(_))
+        ));
+    }
+
+    #[test]
+    fn test_epochs() {
+        let json = r#"{
+            "name": "evolving",
+            "rules": [{ "rule": "length", "words": 4 }],
+            "epochs": [
+                { "height": 10, "rules": [{ "rule": "length", "words": 5 }], "dictionary": "words.txt" },
+                { "height": 20, "rules": [{ "rule": "length", "words": 6 }] }
+            ]
+        }"#;
+        let path = write_spec("spec_epochs", json);
+        fs::write(path.with_file_name("words.txt"), "ant\nbee\n").unwrap();
+
+        let spec = ChainSpec::load(&path).unwrap();
+        assert_eq!(spec.rules_at(9), &[WordRule::Length { words: 4 }]);
+        assert_eq!(spec.rules_at(10), &[WordRule::Length { words: 5 }]);
+        assert_eq!(spec.rules_at(25), &[WordRule::Length { words: 6 }]);
+
+        assert_eq!(spec.dictionary_at(9).len(), DICTIONARY.len());
+        assert_eq!(spec.dictionary_at(10).len(), 2);
+        assert_eq!(spec.dictionary_at(25).len(), 2);
+
+        // Same spec, other words
+        fs::write(path.with_file_name("words.txt"), "ant\ncat\n").unwrap();
+        assert_ne!(ChainSpec::load(&path).unwrap().digest(), spec.digest());
+
+        let json = r#"{
+            "name": "unordered",
+            "rules": [],
+            "epochs": [
+                { "height": 20, "rules": [] },
+                { "height": 10, "rules": [] }
+            ]
+        }"#;
+        let path = write_spec("spec_epochs", json);
+        assert!(matches!(
+            ChainSpec::load(&path),
+            Err(Error::InvalidChainSpec(_))
+        ));
+    }
+}
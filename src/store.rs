@@ -30,7 +30,14 @@ impl DiscStore {
         db.get(k)
     }
 
-    #[allow(dead_code)]
+    pub fn delete(&self, k: &[u8]) -> Result<(), Error> {
+        let mut db = self.db.lock().unwrap();
+
+        db.delete(k)?;
+
+        Ok(())
+    }
+
     pub fn flush(&self) -> Result<(), Error> {
         let mut db = self.db.lock().unwrap();
 
@@ -69,4 +76,13 @@ mod tests {
         assert_eq!(disc_store.get(&[100, 101]), Some(vec![11, 13]));
         assert!(disc_store.get(&[10, 11]).is_none());
     }
+
+    #[test]
+    fn test_delete() {
+        let disc_store = Arc::clone(&DISC_STORE);
+
+        assert!(disc_store.put(&[200, 201], &[17, 19]).is_ok());
+        assert!(disc_store.delete(&[200, 201]).is_ok());
+        assert!(disc_store.get(&[200, 201]).is_none());
+    }
 }